@@ -0,0 +1,87 @@
+//! Measure random pixel access on growing stencils and canvases.
+//!
+//! Run with `cargo bench -p canvas`, the time per pixel should stay flat as
+//! the size grows.
+
+use canvas::{Addressing, Canvas, Samplable, Sampling, Stencil, Transformable};
+use color::Channel;
+use std::time::{Duration, Instant};
+use vek::{geom::repr_c::Rect, mat::repr_c::column_major::Mat3, vec::repr_c::vec2::Vec2};
+
+const SIZES: [i32; 4] = [128, 256, 512, 1024];
+
+/// Build a checkered stencil where every other pixel is masked out
+fn checkered(size: i32) -> Stencil {
+	let buffer = (0..size * size)
+		.flat_map(|i| {
+			let (x, y) = (i % size, i / size);
+			let alpha = if (x + y) % 2 == 0 { 255 } else { 0 };
+			vec![(i % 251) as u8, alpha]
+		})
+		.collect();
+	Stencil::from_buffer_mask_alpha(Rect::new(0, 0, size, size), Channel::Lumaa, buffer)
+}
+
+fn report(name: &str, size: i32, elapsed: Duration) {
+	let pixels = (size * size) as f64;
+	println!(
+		"{:<24} {:>5}x{:<5} {:>10.2} ms {:>8.1} ns/px",
+		name,
+		size,
+		size,
+		elapsed.as_secs_f64() * 1e3,
+		elapsed.as_secs_f64() * 1e9 / pixels
+	);
+}
+
+fn bench<F: FnMut()>(name: &str, size: i32, mut f: F) {
+	let start = Instant::now();
+	f();
+	report(name, size, start.elapsed());
+}
+
+fn main() {
+	for &size in SIZES.iter() {
+		let stencil = checkered(size);
+		let canvas = Canvas::from_stencil(stencil.clone());
+		let mut out = vec![0u8; 2];
+
+		bench("stencil sample2d", size, || {
+			for y in 0..size {
+				for x in 0..size {
+					let _ = stencil.sample2d(
+						(x as f32 + 0.25, y as f32 + 0.75),
+						Sampling::Bilinear,
+						Addressing::Clamp,
+						&mut out,
+					);
+				}
+			}
+		});
+		bench("canvas sample2d", size, || {
+			for y in 0..size {
+				for x in 0..size {
+					let _ = canvas.sample2d(
+						(x as f32 + 0.25, y as f32 + 0.75),
+						Sampling::Bilinear,
+						Addressing::Clamp,
+						&mut out,
+					);
+				}
+			}
+		});
+		bench("canvas copy_to_stencil", size, || {
+			let _ = canvas.copy_to_stencil();
+		});
+		let pivot = Vec2::new(size as f32 / 2., size as f32 / 2.);
+		let rotation = Mat3::rotation_z(30f32.to_radians());
+		bench("stencil transform", size, || {
+			let _ =
+				stencil.transform_around(Sampling::Bilinear, Addressing::Border, pivot, &rotation);
+		});
+		bench("canvas transform", size, || {
+			let _ =
+				canvas.transform_around(Sampling::Bilinear, Addressing::Border, pivot, &rotation);
+		});
+	}
+}
@@ -0,0 +1,389 @@
+use crate::{alpha_component, component_layout, Canvas, Stencil};
+use color::Channel;
+
+/// Per-pixel color adjustment
+///
+/// Only 8-bit color components are adjusted, alpha and float components such
+/// as normals are left untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Adjustment {
+	/// Rotate hue by degrees, scale saturation and lightness in `[-1, 1]`
+	HueSaturation {
+		hue: f32,
+		saturation: f32,
+		lightness: f32,
+	},
+	/// Shift brightness and stretch contrast, both in `[-1, 1]`
+	BrightnessContrast { brightness: f32, contrast: f32 },
+	/// Remap `[black, white]` to the full range with a gamma correction
+	Levels { black: u8, white: u8, gamma: f32 },
+	/// Smooth monotone curve through `(input, output)` control points
+	Curves(Vec<(u8, u8)>),
+	/// Reduce every component to a number of levels
+	Posterize(u8),
+	/// Invert every component
+	Invert,
+}
+
+impl Adjustment {
+	/// Lookup table of adjustments applied to each component independently
+	fn lookup(&self) -> Option<[u8; 256]> {
+		let map: Box<dyn Fn(f32) -> f32> = match self {
+			Adjustment::HueSaturation { .. } => return None,
+			Adjustment::BrightnessContrast {
+				brightness,
+				contrast,
+			} => {
+				let contrast = contrast.clamp(-1., 0.99);
+				let factor = (1. + contrast) / (1. - contrast);
+				let brightness = *brightness;
+				Box::new(move |v| (v + brightness - 0.5) * factor + 0.5)
+			}
+			Adjustment::Levels {
+				black,
+				white,
+				gamma,
+			} => {
+				let black = *black as f32 / 255.;
+				let range = (*white as f32 / 255. - black).max(1. / 255.);
+				let exponent = 1. / gamma.max(0.01);
+				Box::new(move |v| ((v - black) / range).clamp(0., 1.).powf(exponent))
+			}
+			Adjustment::Curves(points) => {
+				let curve = monotone_curve(points);
+				Box::new(move |v| curve(v * 255.) / 255.)
+			}
+			Adjustment::Posterize(levels) => {
+				let steps = (levels.max(&2) - 1) as f32;
+				Box::new(move |v| (v * steps).round() / steps)
+			}
+			Adjustment::Invert => Box::new(|v| 1. - v),
+		};
+		let mut table = [0u8; 256];
+		for (i, value) in table.iter_mut().enumerate() {
+			*value = (map(i as f32 / 255.) * 255.).round().clamp(0., 255.) as u8;
+		}
+		Some(table)
+	}
+
+	/// Adjust the color components of a pixel in place
+	fn apply(&self, channel: Channel, table: Option<&[u8; 256]>, pixel: &mut [u8]) {
+		let (bytes, _) = component_layout(channel);
+		let alpha = alpha_component(channel);
+		let colors = match alpha {
+			Some(_) => bytes - 1,
+			None => bytes,
+		};
+		match (self, table) {
+			(_, Some(table)) => {
+				for value in pixel.iter_mut().take(colors) {
+					*value = table[*value as usize];
+				}
+			}
+			(
+				Adjustment::HueSaturation {
+					hue,
+					saturation,
+					lightness,
+				},
+				None,
+			) => {
+				if colors == 3 {
+					let (h, s, l) = rgb_to_hsl(pixel[0], pixel[1], pixel[2]);
+					let h = (h + hue).rem_euclid(360.);
+					let s = (s * (1. + saturation)).clamp(0., 1.);
+					let (r, g, b) = hsl_to_rgb(h, s, shift_lightness(l, *lightness));
+					pixel[0] = r;
+					pixel[1] = g;
+					pixel[2] = b;
+				} else if colors == 1 {
+					let l = shift_lightness(pixel[0] as f32 / 255., *lightness);
+					pixel[0] = (l * 255.).round() as u8;
+				}
+			}
+			_ => {}
+		}
+	}
+}
+
+/// Move lightness toward white or black
+fn shift_lightness(l: f32, amount: f32) -> f32 {
+	let amount = amount.clamp(-1., 1.);
+	if amount > 0. {
+		l + amount * (1. - l)
+	} else {
+		l * (1. + amount)
+	}
+}
+
+/// Convert to hue in degrees, saturation and lightness in `[0, 1]`
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+	let (r, g, b) = (r as f32 / 255., g as f32 / 255., b as f32 / 255.);
+	let max = r.max(g).max(b);
+	let min = r.min(g).min(b);
+	let l = (max + min) / 2.;
+	let d = max - min;
+	if d <= f32::EPSILON {
+		return (0., 0., l);
+	}
+	let s = d / (1. - (2. * l - 1.).abs());
+	let h = if (max - r).abs() <= f32::EPSILON {
+		((g - b) / d).rem_euclid(6.)
+	} else if (max - g).abs() <= f32::EPSILON {
+		(b - r) / d + 2.
+	} else {
+		(r - g) / d + 4.
+	};
+	(h * 60., s, l)
+}
+
+/// Convert back from hue in degrees, saturation and lightness
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+	let c = (1. - (2. * l - 1.).abs()) * s;
+	let x = c * (1. - ((h / 60.).rem_euclid(2.) - 1.).abs());
+	let m = l - c / 2.;
+	let (r, g, b) = match (h / 60.) as u32 {
+		0 => (c, x, 0.),
+		1 => (x, c, 0.),
+		2 => (0., c, x),
+		3 => (0., x, c),
+		4 => (x, 0., c),
+		_ => (c, 0., x),
+	};
+	let to_u8 = |v: f32| ((v + m) * 255.).round().clamp(0., 255.) as u8;
+	(to_u8(r), to_u8(g), to_u8(b))
+}
+
+/// Monotone cubic interpolation through control points (Fritsch-Carlson)
+///
+/// Without points the curve is the identity, outside the points it is flat.
+fn monotone_curve(points: &[(u8, u8)]) -> impl Fn(f32) -> f32 {
+	let mut points: Vec<(f32, f32)> = points.iter().map(|(x, y)| (*x as f32, *y as f32)).collect();
+	points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+	points.dedup_by(|a, b| (a.0 - b.0).abs() <= f32::EPSILON);
+	if points.is_empty() {
+		points = vec![(0., 0.), (255., 255.)];
+	}
+
+	let n = points.len();
+	let slopes: Vec<f32> = points
+		.windows(2)
+		.map(|w| (w[1].1 - w[0].1) / (w[1].0 - w[0].0))
+		.collect();
+	let mut tangents = vec![0f32; n];
+	if n > 1 {
+		tangents[0] = slopes[0];
+		tangents[n - 1] = slopes[n - 2];
+		for i in 1..n - 1 {
+			tangents[i] = if slopes[i - 1] * slopes[i] <= 0. {
+				0.
+			} else {
+				(slopes[i - 1] + slopes[i]) / 2.
+			};
+		}
+		// Limit tangents so the curve never overshoots
+		for (i, slope) in slopes.iter().enumerate() {
+			if slope.abs() <= f32::EPSILON {
+				tangents[i] = 0.;
+				tangents[i + 1] = 0.;
+			} else {
+				let a = tangents[i] / slope;
+				let b = tangents[i + 1] / slope;
+				let h = (a * a + b * b).sqrt();
+				if h > 3. {
+					tangents[i] = 3. / h * a * slope;
+					tangents[i + 1] = 3. / h * b * slope;
+				}
+			}
+		}
+	}
+
+	move |x: f32| {
+		if x <= points[0].0 {
+			return points[0].1;
+		}
+		if x >= points[n - 1].0 {
+			return points[n - 1].1;
+		}
+		let i = points.windows(2).position(|w| x < w[1].0).unwrap();
+		let (x0, y0) = points[i];
+		let (x1, y1) = points[i + 1];
+		let h = x1 - x0;
+		let t = (x - x0) / h;
+		let (t2, t3) = (t * t, t * t * t);
+		(2. * t3 - 3. * t2 + 1.) * y0
+			+ (t3 - 2. * t2 + t) * h * tangents[i]
+			+ (-2. * t3 + 3. * t2) * y1
+			+ (t3 - t2) * h * tangents[i + 1]
+	}
+}
+
+pub trait Adjustable {
+	type Output;
+
+	/// Apply a color adjustment to every pixel
+	fn adjust(&self, adjustment: &Adjustment) -> Self::Output;
+}
+
+impl Adjustable for Stencil {
+	type Output = Stencil;
+
+	fn adjust(&self, adjustment: &Adjustment) -> Self::Output {
+		let channel = self.channel();
+		let table = adjustment.lookup();
+		let mut stencil = self.clone();
+		for (_, _, pixel) in stencil.iter_mut() {
+			adjustment.apply(channel, table.as_ref(), pixel);
+		}
+		stencil
+	}
+}
+
+impl Adjustable for Canvas {
+	type Output = Canvas;
+
+	fn adjust(&self, adjustment: &Adjustment) -> Self::Output {
+		self.map_tiles(None, |tile| tile.adjust(adjustment))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use vek::geom::repr_c::Rect;
+
+	fn adjusted(channel: Channel, buffer: Vec<u8>, adjustment: Adjustment) -> Vec<u8> {
+		let len = (buffer.len() / channel.pixel_stride()) as i32;
+		let stencil = Stencil::from_buffer(Rect::new(0, 0, len, 1), channel, buffer);
+		stencil.adjust(&adjustment).data().clone()
+	}
+
+	#[test]
+	fn invert_posterize() {
+		assert_eq!(
+			adjusted(Channel::Rgba, vec![0, 100, 255, 128], Adjustment::Invert),
+			vec![255, 155, 0, 128]
+		);
+		assert_eq!(
+			adjusted(
+				Channel::Luma,
+				vec![0, 60, 100, 200, 255],
+				Adjustment::Posterize(3)
+			),
+			vec![0, 0, 128, 255, 255]
+		);
+	}
+
+	#[test]
+	fn brightness_contrast() {
+		let neutral = Adjustment::BrightnessContrast {
+			brightness: 0.,
+			contrast: 0.,
+		};
+		assert_eq!(
+			adjusted(Channel::Luma, vec![0, 17, 128, 255], neutral),
+			vec![0, 17, 128, 255]
+		);
+		let adjustment = Adjustment::BrightnessContrast {
+			brightness: 0.2,
+			contrast: 0.,
+		};
+		assert_eq!(
+			adjusted(Channel::Luma, vec![0, 100, 255], adjustment),
+			vec![51, 151, 255]
+		);
+		let adjustment = Adjustment::BrightnessContrast {
+			brightness: 0.,
+			contrast: 0.5,
+		};
+		assert_eq!(
+			adjusted(Channel::Luma, vec![64, 128, 192], adjustment),
+			vec![0, 129, 255]
+		);
+	}
+
+	#[test]
+	fn levels() {
+		let adjustment = Adjustment::Levels {
+			black: 50,
+			white: 150,
+			gamma: 1.,
+		};
+		assert_eq!(
+			adjusted(Channel::Luma, vec![0, 50, 125, 150, 200], adjustment),
+			vec![0, 0, 191, 255, 255]
+		);
+		let adjustment = Adjustment::Levels {
+			black: 0,
+			white: 255,
+			gamma: 2.,
+		};
+		assert_eq!(
+			adjusted(Channel::Luma, vec![0, 64, 255], adjustment),
+			vec![0, 128, 255]
+		);
+	}
+
+	#[test]
+	fn curves() {
+		let identity = Adjustment::Curves(vec![]);
+		assert_eq!(
+			adjusted(Channel::Luma, vec![0, 33, 200], identity),
+			vec![0, 33, 200]
+		);
+		let adjustment = Adjustment::Curves(vec![(0, 0), (128, 192), (255, 255)]);
+		let values = adjusted(Channel::Luma, (0..=255).collect(), adjustment);
+		assert_eq!(values[128], 192);
+		assert!(values.windows(2).all(|w| w[0] <= w[1]));
+		let flat = Adjustment::Curves(vec![(64, 100), (192, 100)]);
+		assert_eq!(
+			adjusted(Channel::Luma, vec![0, 128, 255], flat),
+			vec![100, 100, 100]
+		);
+	}
+
+	#[test]
+	fn hue_saturation() {
+		let adjustment = Adjustment::HueSaturation {
+			hue: 120.,
+			saturation: 0.,
+			lightness: 0.,
+		};
+		assert_eq!(
+			adjusted(Channel::Rgb, vec![255, 0, 0], adjustment),
+			vec![0, 255, 0]
+		);
+		let adjustment = Adjustment::HueSaturation {
+			hue: 0.,
+			saturation: -1.,
+			lightness: 0.,
+		};
+		assert_eq!(
+			adjusted(Channel::Rgba, vec![255, 0, 0, 7], adjustment),
+			vec![128, 128, 128, 7]
+		);
+		let adjustment = Adjustment::HueSaturation {
+			hue: 90.,
+			saturation: 0.,
+			lightness: 0.4,
+		};
+		assert_eq!(
+			adjusted(Channel::Lumaa, vec![100, 9], adjustment),
+			vec![162, 9]
+		);
+	}
+
+	#[test]
+	fn canvas_adjust() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(62, 0, 4, 1),
+			Channel::Lumaa,
+			vec![10, 255, 0, 0, 30, 255, 40, 255],
+		));
+		let inverted = canvas.adjust(&Adjustment::Invert);
+		assert_eq!(inverted.tiles().len(), 2);
+		inverted.check().unwrap();
+		let pixels: Vec<_> = inverted.iter().flatten().copied().collect();
+		assert_eq!(pixels, vec![245, 255, 0, 0, 225, 255, 215, 255]);
+	}
+}
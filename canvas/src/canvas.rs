@@ -1,66 +1,53 @@
-use crate::Stencil;
+use crate::{Stencil, StencilSpanIterator};
 use color::{Blending, Channel, ChannelError, Compositing};
-use rstar::{Envelope, Point, PointDistance, RTree, RTreeObject, AABB};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::sync::Arc;
 use vek::geom::repr_c::Rect;
 
+/// Width and height of a canvas tile
+pub const TILE_SIZE: i32 = 64;
+
+/// Tile coordinate of a canvas, in tile units
+pub type TileIndex = (i32, i32);
+
+/// Offsets to the eight pixels around a pixel
+const NEIGHBOURS: [(i32, i32); 8] = [
+	(-1, -1),
+	(0, -1),
+	(1, -1),
+	(-1, 0),
+	(1, 0),
+	(-1, 1),
+	(0, 1),
+	(1, 1),
+];
+
 #[derive(Debug, Clone)]
 pub struct Canvas {
 	channel: Channel,
 	empty_pixel: Vec<u8>,
-	rtree: Arc<RTree<StencilObject>>,
-	stencils: Vec<Arc<Stencil>>,
+	bounds: Rect<i32, i32>,
+	tiles: BTreeMap<TileIndex, Arc<Stencil>>,
+	dirty: BTreeSet<TileIndex>,
+	area: Option<Rect<i32, i32>>,
 }
 
-#[derive(Debug)]
-struct StencilObject {
-	stencil: Arc<Stencil>,
-}
-
-impl RTreeObject for StencilObject {
-	type Envelope = AABB<[i32; 2]>;
-
-	fn envelope(&self) -> Self::Envelope {
-		let Rect { x, y, w, h } = self.stencil.bounds();
-		AABB::from_corners([x, y], [x + w, y + h])
-	}
-}
-
-impl PointDistance for StencilObject {
-	fn distance_2(
-		&self,
-		point: &<Self::Envelope as Envelope>::Point,
-	) -> <<Self::Envelope as Envelope>::Point as Point>::Scalar {
-		let min = (self as &dyn RTreeObject<Envelope = AABB<[i32; 2]>>)
-			.envelope()
-			.min_point(point);
-		let sub = [min[0] - point[0], min[1] - point[1]];
-		sub[0] * sub[0] + sub[1] * sub[1]
-	}
-
-	fn contains_point(&self, point: &<Self::Envelope as Envelope>::Point) -> bool {
-		(self as &dyn RTreeObject<Envelope = AABB<[i32; 2]>>)
-			.envelope()
-			.contains_point(point)
-	}
-
-	fn distance_2_if_less_or_equal(
-		&self,
-		point: &<Self::Envelope as Envelope>::Point,
-		max_distance_2: <<Self::Envelope as Envelope>::Point as Point>::Scalar,
-	) -> Option<<<Self::Envelope as Envelope>::Point as Point>::Scalar> {
-		let distance_2 = self.distance_2(point);
-		if distance_2 <= max_distance_2 {
-			Some(distance_2)
-		} else {
-			None
-		}
-	}
+/// Memory usage before and after `Canvas::compact`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CompactReport {
+	pub tiles_before: usize,
+	pub tiles_after: usize,
+	pub bytes_before: usize,
+	pub bytes_after: usize,
 }
 
 #[derive(Debug)]
 pub enum CanvasError {
 	ChannelError(ChannelError),
+	InvalidTile(TileIndex),
+	InvalidBounds(Rect<i32, i32>),
 }
 
 impl std::error::Error for CanvasError {}
@@ -69,6 +56,8 @@ impl std::fmt::Display for CanvasError {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match *self {
 			CanvasError::ChannelError(err) => write!(f, "{}", err),
+			CanvasError::InvalidTile((x, y)) => write!(f, "Invalid tile at {}, {}.", x, y),
+			CanvasError::InvalidBounds(bounds) => write!(f, "Invalid bounds {:?}.", bounds),
 		}
 	}
 }
@@ -79,26 +68,100 @@ impl From<ChannelError> for CanvasError {
 	}
 }
 
+/// Retrieve the tile containing a coordinate
+pub fn tile_index(x: i32, y: i32) -> TileIndex {
+	(x.div_euclid(TILE_SIZE), y.div_euclid(TILE_SIZE))
+}
+
+/// Retrieve the area covered by a tile
+pub fn tile_rect(index: TileIndex) -> Rect<i32, i32> {
+	Rect::new(
+		index.0 * TILE_SIZE,
+		index.1 * TILE_SIZE,
+		TILE_SIZE,
+		TILE_SIZE,
+	)
+}
+
+/// Iterate over the tiles overlapping a rectangle
+fn tiles_in_rect(rect: Rect<i32, i32>) -> impl Iterator<Item = TileIndex> {
+	let (x0, y0) = tile_index(rect.x, rect.y);
+	let (x1, y1) = tile_index(rect.x + rect.w - 1, rect.y + rect.h - 1);
+	let empty = rect.w <= 0 || rect.h <= 0;
+	(y0..=y1)
+		.flat_map(move |y| (x0..=x1).map(move |x| (x, y)))
+		.filter(move |_| !empty)
+}
+
+/// Union of tile bounds and the working area, if any
+fn canvas_bounds<'a>(
+	tiles: impl Iterator<Item = &'a Arc<Stencil>>,
+	area: Option<Rect<i32, i32>>,
+) -> Rect<i32, i32> {
+	tiles
+		.map(|tile| tile.bounds())
+		.chain(area.filter(|area| area.w > 0 && area.h > 0))
+		.fold(None, |acc: Option<Rect<i32, i32>>, bounds| match acc {
+			Some(acc) => Some(acc.union(bounds)),
+			None => Some(bounds),
+		})
+		.or(area)
+		.unwrap_or_else(|| Rect::new(0, 0, 0, 0))
+}
+
 impl Canvas {
 	/// Retrieve channel
 	pub fn channel(&self) -> Channel {
 		self.channel
 	}
 
-	/// Retrieve stencils
-	pub fn stencils(&self) -> &Vec<Arc<Stencil>> {
-		&self.stencils
+	/// Retrieve tiles
+	pub fn tiles(&self) -> &BTreeMap<TileIndex, Arc<Stencil>> {
+		&self.tiles
+	}
+
+	/// Retrieve tiles modified since the last call to `clear_dirty`
+	pub fn dirty_tiles(&self) -> &BTreeSet<TileIndex> {
+		&self.dirty
+	}
+
+	/// Forget about modified tiles, usually once they have been persisted
+	pub fn clear_dirty(&mut self) {
+		self.dirty.clear();
+	}
+
+	/// Retrieve the working area, which bounds stay within even where no
+	/// pixel is painted
+	pub fn area(&self) -> Option<Rect<i32, i32>> {
+		self.area
+	}
+
+	/// Set the working area, without dropping pixels outside of it
+	pub fn with_area(&self, area: Option<Rect<i32, i32>>) -> Canvas {
+		let mut canvas = self.clone();
+		canvas.area = area;
+		canvas.update_bounds();
+		canvas
 	}
 
 	/// Create a canvas from raw part
+	///
+	/// Stencils are split along tile boundaries, stencils already fitting in a
+	/// single tile are shared as is.
 	pub unsafe fn from_raw_parts(channel: Channel, stencils: Vec<Arc<Stencil>>) -> Self {
 		let mut canvas = Canvas {
 			channel,
 			empty_pixel: channel.default_pixel(),
-			rtree: Arc::new(RTree::new()),
-			stencils,
+			bounds: Rect::new(0, 0, 0, 0),
+			tiles: BTreeMap::new(),
+			dirty: BTreeSet::new(),
+			area: None,
 		};
-		canvas.rebuild_rtree_from_stencils();
+		for stencil in stencils {
+			canvas.insert_stencil(stencil, Blending::Normal, Compositing::Lighter);
+		}
+		canvas.dirty.clear();
+		canvas.update_bounds();
 		canvas
 	}
 
@@ -119,6 +182,9 @@ impl Canvas {
 
 	/// Apply a stencil on this canvas by blending the stencil on top
 	/// of previous stencils.
+	///
+	/// Only the tiles overlapped by the stencil are copied, the others are
+	/// shared with this canvas.
 	pub fn apply_stencil_with_blend(
 		&self,
 		stencil: Stencil,
@@ -131,50 +197,187 @@ impl Canvas {
 				stencil.channel(),
 			)));
 		}
-		let channel = self.channel;
-		let mut stencils: Vec<Arc<Stencil>> = Vec::with_capacity(self.stencils.len());
+		let mut canvas = self.clone();
+		canvas.insert_stencil(Arc::new(stencil), blend_mode, compose_op);
+		canvas.update_bounds();
+		Ok(canvas)
+	}
+
+	/// Split a stencil along tile boundaries and merge each part in its tile
+	fn insert_stencil(
+		&mut self,
+		stencil: Arc<Stencil>,
+		blend_mode: Blending,
+		compose_op: Compositing,
+	) {
 		let bounds = stencil.bounds();
-		let mut merged = false;
-		for old_stencil in self.stencils.iter() {
-			let old_bounds = old_stencil.bounds();
-			if bounds.contains_rect(old_bounds) || bounds.collides_with_rect(old_bounds) {
-				let new_stencil = Stencil::merge(old_stencil, &stencil, blend_mode, compose_op);
-				stencils.push(Arc::new(new_stencil));
-				merged = true;
-			} else {
-				stencils.push(old_stencil.clone());
-			}
+		let single = tile_index(bounds.x, bounds.y)
+			== tile_index(bounds.x + bounds.w - 1, bounds.y + bounds.h - 1);
+		let indices: Vec<TileIndex> = tiles_in_rect(bounds).collect();
+
+		#[cfg(feature = "rayon")]
+		let iter = indices.into_par_iter();
+		#[cfg(not(feature = "rayon"))]
+		let iter = indices.into_iter();
+
+		let tiles = &self.tiles;
+		let merged: Vec<(TileIndex, Arc<Stencil>)> = iter
+			.map(|index| {
+				let part = if single {
+					stencil.clone()
+				} else {
+					Arc::new(stencil.crop(tile_rect(index)))
+				};
+				let tile = match tiles.get(&index) {
+					Some(old) => Arc::new(Stencil::merge(old, &part, blend_mode, compose_op)),
+					None => part,
+				};
+				(index, tile)
+			})
+			.collect();
+		for (index, tile) in merged {
+			self.tiles.insert(index, tile);
+			self.dirty.insert(index);
 		}
-		if !merged {
-			stencils.push(Arc::new(stencil));
+	}
+
+	/// Replace every tile overlapping region by its image through `f`, which
+	/// must stay within the tile. Other tiles are shared with this canvas.
+	pub(crate) fn map_tiles<F>(&self, region: Option<Rect<i32, i32>>, f: F) -> Canvas
+	where
+		F: Fn(&Stencil) -> Stencil + Send + Sync,
+	{
+		let tiles: Vec<(&TileIndex, &Arc<Stencil>)> = self
+			.tiles
+			.iter()
+			.filter(|(_, tile)| match region {
+				Some(region) => {
+					region.contains_rect(tile.bounds()) || region.collides_with_rect(tile.bounds())
+				}
+				None => true,
+			})
+			.collect();
+
+		#[cfg(feature = "rayon")]
+		let iter = tiles.into_par_iter();
+		#[cfg(not(feature = "rayon"))]
+		let iter = tiles.into_iter();
+
+		let mapped: Vec<(TileIndex, Arc<Stencil>)> = iter
+			.map(|(index, tile)| (*index, Arc::new(f(tile))))
+			.collect();
+		let mut canvas = self.clone();
+		for (index, tile) in mapped {
+			debug_assert!(tile_rect(index).contains_rect(tile.bounds()));
+			canvas.tiles.insert(index, tile);
+			canvas.dirty.insert(index);
 		}
+		canvas.update_bounds();
+		canvas
+	}
 
-		let mut canvas = Canvas {
-			channel,
-			empty_pixel: channel.default_pixel(),
-			rtree: Arc::new(RTree::new()),
-			stencils,
+	/// Recalculate bounds from tiles and the working area
+	fn update_bounds(&mut self) {
+		self.bounds = canvas_bounds(self.tiles.values(), self.area);
+	}
+
+	/// Approximate heap memory used by the tiles, in bytes
+	pub fn heap_size(&self) -> usize {
+		self.tiles.values().map(|tile| tile.heap_size()).sum()
+	}
+
+	/// Shrink every tile to its visible pixels and drop empty tiles
+	///
+	/// Applied stencils are already merged into a single stencil per tile, so
+	/// there is nothing left to merge. Splitting sparse tiles into smaller
+	/// stencils is not done, a tile mask being at most 512 bytes. Tiles that
+	/// are already compact are shared with this canvas.
+	pub fn compact(&self) -> (Canvas, CompactReport) {
+		let mut canvas = self.clone();
+		canvas.tiles.clear();
+		for (index, tile) in self.tiles.iter() {
+			let compacted = tile.compact();
+			if compacted.data().is_empty() {
+				canvas.dirty.insert(*index);
+			} else if compacted.bounds() == tile.bounds()
+				&& compacted.data().len() == tile.data().len()
+			{
+				canvas.tiles.insert(*index, tile.clone());
+			} else {
+				canvas.tiles.insert(*index, Arc::new(compacted));
+				canvas.dirty.insert(*index);
+			}
+		}
+		canvas.update_bounds();
+		let report = CompactReport {
+			tiles_before: self.tiles.len(),
+			tiles_after: canvas.tiles.len(),
+			bytes_before: self.heap_size(),
+			bytes_after: canvas.heap_size(),
 		};
-		canvas.rebuild_rtree_from_stencils();
-		Ok(canvas)
+		(canvas, report)
 	}
 
-	/// Rebuild RTree from brushes
-	fn rebuild_rtree_from_stencils(&mut self) {
-		self.rtree = Arc::new(RTree::bulk_load(
-			self.stencils
-				.iter()
-				.map(|stencil| StencilObject {
-					stencil: stencil.clone(),
-				})
-				.collect::<Vec<_>>(),
-		));
+	/// Smallest rect containing every visible pixel
+	///
+	/// Unlike `bounds`, this ignores masked-in pixels with a zero alpha.
+	pub fn tight_bounds(&self) -> Rect<i32, i32> {
+		self.tiles
+			.values()
+			.map(|tile| tile.tight_bounds())
+			.filter(|bounds| bounds.w > 0 && bounds.h > 0)
+			.fold(None, |acc: Option<Rect<i32, i32>>, bounds| match acc {
+				Some(acc) => Some(acc.union(bounds)),
+				None => Some(bounds),
+			})
+			.unwrap_or_else(|| Rect::new(0, 0, 0, 0))
+	}
+
+	/// Crop every tile to the visible pixels and drop tiles left empty
+	///
+	/// Tiles that lie within the visible area are shared with this canvas.
+	/// The working area, if any, is kept.
+	pub fn trim(&self) -> Canvas {
+		let bounds = self.tight_bounds();
+		let mut canvas = self.clone();
+		canvas.tiles.clear();
+		for (index, tile) in self.tiles.iter() {
+			let trimmed = tile.crop(bounds);
+			if trimmed.data().is_empty() {
+				canvas.dirty.insert(*index);
+			} else if trimmed.bounds() == tile.bounds() {
+				canvas.tiles.insert(*index, tile.clone());
+			} else {
+				canvas.tiles.insert(*index, Arc::new(trimmed));
+				canvas.dirty.insert(*index);
+			}
+		}
+		canvas.update_bounds();
+		canvas
+	}
+
+	/// Verify that every tile holds a single consistent stencil of this
+	/// channel that stays within the tile, so no two stencils ever overlap.
+	pub fn check(&self) -> Result<(), CanvasError> {
+		for (index, tile) in self.tiles.iter() {
+			let rect = tile_rect(*index);
+			if tile.channel() != self.channel
+				|| !tile.is_consistent()
+				|| !rect.contains_rect(tile.bounds())
+			{
+				return Err(CanvasError::InvalidTile(*index));
+			}
+		}
+		if canvas_bounds(self.tiles.values(), self.area) != self.bounds {
+			return Err(CanvasError::InvalidBounds(self.bounds));
+		}
+		Ok(())
 	}
 
 	/// Try to retrieve a pixel at coordinate
 	pub fn try_get(&self, x: i32, y: i32) -> Option<&[u8]> {
-		if let Some(node) = self.rtree.locate_at_point(&[x, y]) {
-			node.stencil.try_get(x, y)
+		if let Some(tile) = self.tiles.get(&tile_index(x, y)) {
+			tile.try_get(x, y)
 		} else {
 			None
 		}
@@ -182,14 +385,7 @@ impl Canvas {
 
 	/// Retrieve canvas bounds
 	pub fn bounds(&self) -> Rect<i32, i32> {
-		if self.stencils.len() == 0 {
-			Rect::new(0, 0, 0, 0)
-		} else {
-			let aabb = self.rtree.root().envelope();
-			let lower = aabb.lower();
-			let upper = aabb.upper();
-			Rect::new(lower[0], lower[1], upper[0] - lower[0], upper[1] - lower[1])
-		}
+		self.bounds
 	}
 
 	/// Iterate over each pixel of this canvas
@@ -210,32 +406,127 @@ impl Canvas {
 		}
 	}
 
+	/// Iterate over runs of contiguous pixels within region, row by row
+	///
+	/// Runs are split at tile boundaries.
+	pub fn spans(&self, region: Rect<i32, i32>) -> CanvasSpanIterator<'_> {
+		CanvasSpanIterator {
+			canvas: self,
+			region,
+			y: region.y,
+			tile_x: tile_index(region.x, region.y).0,
+			spans: None,
+		}
+	}
+
 	/// Allocate a copy of this canvas
 	pub fn copy_to_stencil(&self) -> Stencil {
-		Stencil::from_buffer(
-			self.bounds(),
-			self.channel,
-			self.iter().flatten().map(|b| *b).collect::<Vec<u8>>(),
-		)
+		self.copy_region_to_stencil(self.bounds())
 	}
 
-	/// Crop canvas
+	/// Allocate a copy of region, filling uncovered pixels with the empty pixel
+	pub fn copy_region_to_stencil(&self, region: Rect<i32, i32>) -> Stencil {
+		let stride = self.channel.pixel_stride();
+		let mut buffer: Vec<u8> = Vec::with_capacity((region.w * region.h) as usize * stride);
+		for _ in 0..(region.w * region.h) {
+			buffer.extend_from_slice(&self.empty_pixel);
+		}
+		let pitch = (region.w as usize * stride).max(1);
+
+		#[cfg(feature = "rayon")]
+		let rows = buffer.par_chunks_mut(pitch);
+		#[cfg(not(feature = "rayon"))]
+		let rows = buffer.chunks_mut(pitch);
+
+		rows.enumerate().for_each(|(y, row)| {
+			let row_region = Rect::new(region.x, region.y + y as i32, region.w, 1);
+			for (_, start, _, data) in self.spans(row_region) {
+				let offset = (start - region.x) as usize * stride;
+				row[offset..offset + data.len()].copy_from_slice(data);
+			}
+		});
+		Stencil::from_buffer(region, self.channel, buffer)
+	}
+
+	/// Crop canvas to the pixels touching region
+	///
+	/// Stencils are merged once applied, so each group of masked-in pixels
+	/// touching one another, diagonals included, stands for a stencil. Groups
+	/// overlapping region are kept whole, like whole stencils, and the rest is
+	/// dropped. Tiles kept whole are shared with this canvas.
 	pub fn crop(&self, region: Rect<i32, i32>) -> Self {
-		let mut canvas = self.clone();
-		canvas.stencils = canvas
-			.stencils
-			.drain(..)
-			.filter_map(|stencil| {
-				if region.contains_rect(stencil.bounds())
-					|| region.collides_with_rect(stencil.bounds())
-				{
-					Some(stencil.clone())
-				} else {
-					None
-				}
+		let mut stack: Vec<(i32, i32)> = tiles_in_rect(region)
+			.filter_map(|index| self.tiles.get(&index))
+			.flat_map(|tile| {
+				tile.crop(region)
+					.iter()
+					.map(|(x, y, _)| (x, y))
+					.collect::<Vec<_>>()
 			})
 			.collect();
-		canvas.rebuild_rtree_from_stencils();
+		let mut kept: HashSet<(i32, i32)> = stack.iter().copied().collect();
+		while let Some((x, y)) = stack.pop() {
+			for (dx, dy) in NEIGHBOURS.iter() {
+				let next = (x + dx, y + dy);
+				if !kept.contains(&next) && self.try_get(next.0, next.1).is_some() {
+					kept.insert(next);
+					stack.push(next);
+				}
+			}
+		}
+
+		let mut rects: BTreeMap<TileIndex, (Rect<i32, i32>, usize)> = BTreeMap::new();
+		for (x, y) in kept.iter().copied() {
+			let (rect, count) = rects
+				.entry(tile_index(x, y))
+				.or_insert((Rect::new(x, y, 1, 1), 0));
+			*rect = rect.union(Rect::new(x, y, 1, 1));
+			*count += 1;
+		}
+
+		let mut canvas = self.clone();
+		canvas.tiles.clear();
+		for (index, tile) in self.tiles.iter() {
+			match rects.get(index) {
+				Some((_, count)) if *count == tile.iter().count() => {
+					canvas.tiles.insert(*index, tile.clone());
+					continue;
+				}
+				Some((rect, _)) => {
+					let cropped = Stencil::from_fn(*rect, self.channel, |x, y, pixel| {
+						if kept.contains(&(x, y)) {
+							pixel.copy_from_slice(tile.try_get(x, y).unwrap());
+							true
+						} else {
+							false
+						}
+					});
+					canvas.tiles.insert(*index, Arc::new(cropped));
+				}
+				None => {}
+			}
+			canvas.dirty.insert(*index);
+		}
+		canvas.update_bounds();
+		canvas
+	}
+
+	/// Clip canvas to region, dropping every pixel outside of it
+	pub(crate) fn clip(&self, region: Rect<i32, i32>) -> Self {
+		let mut canvas = self.clone();
+		canvas.tiles.clear();
+		for (index, tile) in self.tiles.iter() {
+			if region.contains_rect(tile.bounds()) {
+				canvas.tiles.insert(*index, tile.clone());
+				continue;
+			}
+			let cropped = tile.crop(region);
+			if !cropped.data().is_empty() {
+				canvas.tiles.insert(*index, Arc::new(cropped));
+			}
+			canvas.dirty.insert(*index);
+		}
+		canvas.update_bounds();
 		canvas
 	}
 }
@@ -253,6 +544,53 @@ impl std::ops::Index<(i32, i32)> for Canvas {
 	}
 }
 
+/// Iterator over `(y, x_start, x_end, data)` runs, `x_end` being exclusive
+pub struct CanvasSpanIterator<'canvas> {
+	canvas: &'canvas Canvas,
+	region: Rect<i32, i32>,
+	y: i32,
+	tile_x: i32,
+	spans: Option<StencilSpanIterator<'canvas>>,
+}
+
+impl<'canvas> Iterator for CanvasSpanIterator<'canvas> {
+	type Item = (i32, i32, i32, &'canvas [u8]);
+
+	fn next(&mut self) -> Option<(i32, i32, i32, &'canvas [u8])> {
+		let left = self.region.x;
+		let right = self.region.x + self.region.w;
+		let stride = self.canvas.channel.pixel_stride();
+		if self.region.w <= 0 {
+			return None;
+		}
+		while self.y < self.region.y + self.region.h {
+			if let Some(spans) = &mut self.spans {
+				for (y, start, end, data) in spans {
+					let (clip_start, clip_end) = (start.max(left), end.min(right));
+					if clip_start < clip_end {
+						let data = &data[((clip_start - start) as usize * stride)
+							..((clip_end - start) as usize * stride)];
+						return Some((y, clip_start, clip_end, data));
+					}
+				}
+				self.spans = None;
+				self.tile_x += 1;
+			}
+			if self.tile_x > tile_index(right - 1, self.y).0 {
+				self.y += 1;
+				self.tile_x = tile_index(left, self.y).0;
+				continue;
+			}
+			let index = (self.tile_x, tile_index(left, self.y).1);
+			match self.canvas.tiles.get(&index) {
+				Some(tile) => self.spans = Some(tile.row_spans(self.y)),
+				None => self.tile_x += 1,
+			}
+		}
+		None
+	}
+}
+
 pub struct CanvasIterator<'canvas> {
 	canvas: &'canvas Canvas,
 	region: Rect<i32, i32>,
@@ -378,4 +716,246 @@ mod tests {
 		let pixels: Vec<_> = c.iter().flatten().map(|b| *b).collect();
 		assert_eq!(pixels, vec![1, 255, 0, 0, 0, 0, 4, 1]);
 	}
+
+	#[test]
+	fn crop_across_tiles() {
+		let canvas = Canvas::from_stencil(Stencil::from_fn(
+			Rect::new(60, 0, 10, 1),
+			Channel::Luma,
+			|_, _, _| true,
+		))
+		.apply_stencil(Stencil::from_fn(
+			Rect::new(72, 0, 2, 1),
+			Channel::Luma,
+			|_, _, _| true,
+		))
+		.unwrap();
+		// The stroke spans two tiles and is kept whole, the apart pixels are not
+		let cropped = canvas.crop(Rect::new(60, 0, 1, 1));
+		assert_eq!(cropped.bounds(), Rect::new(60, 0, 10, 1));
+		assert_eq!(cropped.tiles().len(), 2);
+		assert!(cropped.check().is_ok());
+		assert!(canvas.crop(Rect::new(0, 20, 2, 2)).tiles().is_empty());
+	}
+
+	#[test]
+	fn overlapping_strokes() {
+		// Two separate stencils in the same tile, bridged by a stroke
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 2, 1),
+			Channel::Luma,
+			vec![10, 10],
+		))
+		.apply_stencil(Stencil::from_buffer(
+			Rect::new(4, 0, 2, 1),
+			Channel::Luma,
+			vec![20, 20],
+		))
+		.unwrap();
+		let b = a
+			.apply_stencil(Stencil::from_buffer(
+				Rect::new(1, 0, 4, 1),
+				Channel::Luma,
+				vec![1, 1, 1, 1],
+			))
+			.unwrap();
+		b.check().unwrap();
+		assert_eq!(b.tiles().len(), 1);
+		let pixels: Vec<_> = b.iter().flatten().copied().collect();
+		assert_eq!(pixels, vec![10, 11, 1, 1, 21, 20]);
+
+		// A stroke crossing the corner of four tiles, applied twice
+		let stroke = || {
+			Stencil::from_buffer_mask_alpha(
+				Rect::new(62, 62, 4, 4),
+				Channel::Lumaa,
+				(0..16)
+					.flat_map(|i| vec![1, if i % 5 == 0 { 0 } else { 255 }])
+					.collect(),
+			)
+		};
+		let c = Canvas::new(Channel::Lumaa)
+			.apply_stencil(Stencil::from_buffer(
+				Rect::new(60, 60, 8, 8),
+				Channel::Lumaa,
+				(0..64).flat_map(|_| vec![10, 255]).collect(),
+			))
+			.unwrap()
+			.apply_stencil(stroke())
+			.unwrap()
+			.apply_stencil(stroke())
+			.unwrap();
+		c.check().unwrap();
+		assert_eq!(c.tiles().len(), 4);
+		assert_eq!(c.bounds(), Rect::new(60, 60, 8, 8));
+		for y in 60..68 {
+			for x in 60..68 {
+				let inside = (62..66).contains(&x) && (62..66).contains(&y);
+				let masked = inside && ((y - 62) * 4 + x - 62) % 5 == 0;
+				let expected = if inside && !masked { 12 } else { 10 };
+				assert_eq!(c[(x, y)][0], expected, "pixel at {}, {}", x, y);
+			}
+		}
+	}
+
+	#[test]
+	fn compact() {
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 70, 2),
+			Channel::Lumaa,
+			(0..140)
+				.flat_map(|i| {
+					if i == 1 || i == 69 {
+						vec![1, 255]
+					} else {
+						vec![0, 0]
+					}
+				})
+				.collect(),
+		))
+		.apply_stencil(Stencil::new(Rect::new(200, 200, 8, 8), Channel::Lumaa))
+		.unwrap();
+		assert_eq!(a.tiles().len(), 3);
+		assert_eq!(a.bounds(), Rect::new(0, 0, 208, 208));
+
+		let (b, report) = a.compact();
+		b.check().unwrap();
+		assert_eq!(b.tiles().len(), 2);
+		assert_eq!(b.bounds(), Rect::new(1, 0, 69, 1));
+		assert_eq!(b.tiles()[&(0, 0)].bounds(), Rect::new(1, 0, 1, 1));
+		assert_eq!(b.tiles()[&(1, 0)].bounds(), Rect::new(69, 0, 1, 1));
+		assert_eq!(b[(1, 0)], [1, 255]);
+		assert_eq!(b[(69, 0)], [1, 255]);
+		assert_eq!(
+			report,
+			CompactReport {
+				tiles_before: 3,
+				tiles_after: 2,
+				bytes_before: a.heap_size(),
+				bytes_after: b.heap_size(),
+			}
+		);
+		assert!(report.bytes_after < report.bytes_before);
+
+		// Compacting again keeps every tile
+		let (c, report) = b.compact();
+		assert_eq!(report.bytes_after, report.bytes_before);
+		assert!(Arc::ptr_eq(&b.tiles()[&(0, 0)], &c.tiles()[&(0, 0)]));
+	}
+
+	#[test]
+	fn trim() {
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 70, 2),
+			Channel::Lumaa,
+			(0..140)
+				.flat_map(|i| match i {
+					1 | 69 => vec![1, 255],
+					2 => vec![2, 0],
+					_ => vec![0, 0],
+				})
+				.collect(),
+		))
+		.apply_stencil(Stencil::new(Rect::new(200, 200, 8, 8), Channel::Lumaa))
+		.unwrap();
+		assert_eq!(a.bounds(), Rect::new(0, 0, 208, 208));
+		assert_eq!(a.tight_bounds(), Rect::new(1, 0, 69, 1));
+
+		let b = a.trim();
+		b.check().unwrap();
+		assert_eq!(b.bounds(), Rect::new(1, 0, 69, 1));
+		assert_eq!(b.tiles().len(), 2);
+		assert_eq!(b[(1, 0)], [1, 255]);
+		assert_eq!(b.try_get(2, 0), Some(&[2, 0][..]));
+		assert_eq!(b.try_get(1, 1), None);
+
+		let c = b.trim();
+		assert!(Arc::ptr_eq(&b.tiles()[&(0, 0)], &c.tiles()[&(0, 0)]));
+		assert_eq!(
+			Canvas::new(Channel::Rgba).trim().bounds(),
+			Rect::new(0, 0, 0, 0)
+		);
+	}
+
+	#[test]
+	fn spans() {
+		let a = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(62, 0, 4, 2),
+			Channel::Lumaa,
+			vec![1, 255, 2, 255, 3, 255, 4, 255, 5, 255, 0, 0, 7, 255, 8, 255],
+		));
+		let spans: Vec<_> = a.spans(a.bounds()).collect();
+		assert_eq!(
+			spans,
+			vec![
+				(0, 62, 64, &[1, 255, 2, 255][..]),
+				(0, 64, 66, &[3, 255, 4, 255][..]),
+				(1, 62, 63, &[5, 255][..]),
+				(1, 64, 66, &[7, 255, 8, 255][..]),
+			]
+		);
+		let spans: Vec<_> = a.spans(Rect::new(63, 1, 10, 10)).collect();
+		assert_eq!(spans, vec![(1, 64, 66, &[7, 255, 8, 255][..])]);
+		let spans: Vec<_> = a.spans(Rect::new(-100, -100, 2, 2)).collect();
+		assert_eq!(spans.len(), 0);
+
+		// Spans cover exactly the pixels of the canvas
+		let mut pixels = vec![0u8; (a.bounds().w * a.bounds().h * 2) as usize];
+		for (y, start, _, data) in a.spans(a.bounds()) {
+			let offset = ((y * a.bounds().w + start - a.bounds().x) * 2) as usize;
+			pixels[offset..offset + data.len()].copy_from_slice(data);
+		}
+		let expected: Vec<_> = a.iter().flatten().copied().collect();
+		assert_eq!(pixels, expected);
+	}
+
+	#[test]
+	fn tiles() {
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(-2, 62, 4, 4),
+			Channel::Luma,
+			(1..=16).collect(),
+		));
+		assert_eq!(a.bounds(), Rect::new(-2, 62, 4, 4));
+		assert_eq!(
+			a.tiles().keys().copied().collect::<Vec<_>>(),
+			vec![(-1, 0), (-1, 1), (0, 0), (0, 1)]
+		);
+		assert_eq!(a.tiles()[&(0, 1)].bounds(), Rect::new(0, 64, 2, 2));
+		let pixels: Vec<_> = a.iter().flatten().copied().collect();
+		assert_eq!(pixels, (1..=16).collect::<Vec<u8>>());
+		a.check().unwrap();
+
+		// A single pixel only touches its own tile
+		let mut a = a;
+		a.clear_dirty();
+		let b = a
+			.apply_stencil(Stencil::from_buffer(
+				Rect::new(1, 63, 1, 1),
+				Channel::Luma,
+				vec![100],
+			))
+			.unwrap();
+		assert_eq!(
+			b.dirty_tiles().iter().copied().collect::<Vec<_>>(),
+			vec![(0, 0)]
+		);
+		assert!(!Arc::ptr_eq(&a.tiles()[&(0, 0)], &b.tiles()[&(0, 0)]));
+		assert!(Arc::ptr_eq(&a.tiles()[&(-1, 0)], &b.tiles()[&(-1, 0)]));
+		assert!(Arc::ptr_eq(&a.tiles()[&(0, 1)], &b.tiles()[&(0, 1)]));
+		assert_eq!(b[(1, 63)], [108]);
+		assert_eq!(a[(1, 63)], [8]);
+
+		// Legacy stencils are split along tile boundaries
+		let c =
+			unsafe { Canvas::from_raw_parts(Channel::Luma, a.tiles().values().cloned().collect()) };
+		assert_eq!(c.tiles().len(), 4);
+		assert!(c.dirty_tiles().is_empty());
+		let c =
+			unsafe { Canvas::from_raw_parts(Channel::Luma, vec![Arc::new(b.copy_to_stencil())]) };
+		assert_eq!(c.tiles().len(), 4);
+		let pixels: Vec<_> = c.iter().flatten().copied().collect();
+		let expected: Vec<_> = b.iter().flatten().copied().collect();
+		assert_eq!(pixels, expected);
+	}
 }
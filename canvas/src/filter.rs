@@ -0,0 +1,378 @@
+use crate::{
+	alpha_component, component_layout, components, write_components, Addressing, Canvas, Stencil,
+};
+use color::Channel;
+use vek::geom::repr_c::Rect;
+
+/// Convolution kernel centered on its middle weight
+#[derive(Debug, Clone, PartialEq)]
+pub struct Kernel {
+	width: usize,
+	height: usize,
+	weights: Vec<f32>,
+}
+
+impl Kernel {
+	/// Create a kernel from row-major weights, width and height must be odd
+	pub fn new(width: usize, height: usize, weights: Vec<f32>) -> Self {
+		assert!(width % 2 == 1 && height % 2 == 1);
+		assert_eq!(width * height, weights.len());
+		Kernel {
+			width,
+			height,
+			weights,
+		}
+	}
+
+	/// Retrieve width
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	/// Retrieve height
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// Retrieve weights
+	pub fn weights(&self) -> &[f32] {
+		&self.weights
+	}
+
+	/// Scale weights so they sum up to one
+	pub fn normalized(self) -> Self {
+		let sum: f32 = self.weights.iter().sum();
+		if sum.abs() <= f32::EPSILON {
+			self
+		} else {
+			let weights = self.weights.iter().map(|w| w / sum).collect();
+			Kernel::new(self.width, self.height, weights)
+		}
+	}
+
+	/// Average of a square of `2 * radius + 1` pixels
+	pub fn box_blur(radius: usize) -> Self {
+		let size = radius * 2 + 1;
+		Kernel::new(size, size, vec![1.; size * size]).normalized()
+	}
+
+	/// Gaussian blur where `radius` covers about three standard deviations
+	pub fn gaussian_blur(radius: usize) -> Self {
+		let size = radius * 2 + 1;
+		let sigma = (radius as f32 / 3.).max(0.5);
+		let weights = (0..size * size)
+			.map(|i| {
+				let x = (i % size) as f32 - radius as f32;
+				let y = (i / size) as f32 - radius as f32;
+				(-(x * x + y * y) / (2. * sigma * sigma)).exp()
+			})
+			.collect();
+		Kernel::new(size, size, weights).normalized()
+	}
+
+	/// Sharpen edges
+	pub fn sharpen() -> Self {
+		Kernel::new(3, 3, vec![0., -1., 0., -1., 5., -1., 0., -1., 0.])
+	}
+
+	/// Emboss toward the bottom right
+	pub fn emboss() -> Self {
+		Kernel::new(3, 3, vec![-2., -1., 0., -1., 1., 1., 0., 1., 2.])
+	}
+
+	/// Horizontal Sobel operator
+	pub fn sobel_x() -> Self {
+		Kernel::new(3, 3, vec![-1., 0., 1., -2., 0., 2., -1., 0., 1.])
+	}
+
+	/// Vertical Sobel operator
+	pub fn sobel_y() -> Self {
+		Kernel::new(3, 3, vec![-1., -2., -1., 0., 0., 0., 1., 2., 1.])
+	}
+
+	/// Iterate over `(dx, dy, weight)` offsets from the center
+	fn taps(&self) -> impl Iterator<Item = (i32, i32, f32)> + '_ {
+		let (rx, ry) = ((self.width / 2) as i32, (self.height / 2) as i32);
+		let width = self.width;
+		self.weights
+			.iter()
+			.enumerate()
+			.map(move |(i, w)| ((i % width) as i32 - rx, (i / width) as i32 - ry, *w))
+	}
+}
+
+/// Convolution filters
+///
+/// Pixels keep their bounds. In channels with alpha, masked out pixels are
+/// transparent and colors are weighted by alpha; in other channels, masked
+/// out pixels stay masked out and missing neighbors repeat the center pixel.
+pub trait Filterable {
+	type Output;
+
+	/// Apply a convolution kernel
+	fn convolve(&self, kernel: &Kernel, addressing: Addressing) -> Self::Output;
+
+	/// Gradient magnitude of the Sobel operators
+	fn sobel(&self, addressing: Addressing) -> Self::Output;
+
+	/// Average neighbors within radius
+	fn box_blur(&self, radius: usize) -> Self::Output {
+		self.convolve(&Kernel::box_blur(radius), Addressing::Border)
+	}
+
+	/// Gaussian blur within radius
+	fn gaussian_blur(&self, radius: usize) -> Self::Output {
+		self.convolve(&Kernel::gaussian_blur(radius), Addressing::Border)
+	}
+
+	/// Sharpen edges
+	fn sharpen(&self) -> Self::Output {
+		self.convolve(&Kernel::sharpen(), Addressing::Clamp)
+	}
+
+	/// Emboss toward the bottom right
+	fn emboss(&self) -> Self::Output {
+		self.convolve(&Kernel::emboss(), Addressing::Clamp)
+	}
+}
+
+impl Filterable for Stencil {
+	type Output = Stencil;
+
+	fn convolve(&self, kernel: &Kernel, addressing: Addressing) -> Self::Output {
+		filter(
+			|x, y| self.try_get(x, y),
+			self.bounds(),
+			self.channel(),
+			&[kernel],
+			addressing,
+		)
+	}
+
+	fn sobel(&self, addressing: Addressing) -> Self::Output {
+		filter(
+			|x, y| self.try_get(x, y),
+			self.bounds(),
+			self.channel(),
+			&[&Kernel::sobel_x(), &Kernel::sobel_y()],
+			addressing,
+		)
+	}
+}
+
+impl Filterable for Canvas {
+	type Output = Canvas;
+
+	fn convolve(&self, kernel: &Kernel, addressing: Addressing) -> Self::Output {
+		Canvas::from_stencil(filter(
+			|x, y| self.try_get(x, y),
+			self.bounds(),
+			self.channel(),
+			&[kernel],
+			addressing,
+		))
+	}
+
+	fn sobel(&self, addressing: Addressing) -> Self::Output {
+		Canvas::from_stencil(filter(
+			|x, y| self.try_get(x, y),
+			self.bounds(),
+			self.channel(),
+			&[&Kernel::sobel_x(), &Kernel::sobel_y()],
+			addressing,
+		))
+	}
+}
+
+/// Convolve every pixel of bounds with one kernel, or with several kernels
+/// combined into their gradient magnitude
+fn filter<'a, F>(
+	fetch: F,
+	bounds: Rect<i32, i32>,
+	channel: Channel,
+	kernels: &[&Kernel],
+	addressing: Addressing,
+) -> Stencil
+where
+	F: Fn(i32, i32) -> Option<&'a [u8]> + Send + Sync,
+{
+	let alpha = alpha_component(channel);
+	let (bytes, floats) = component_layout(channel);
+	let count = bytes + floats;
+
+	// Colors are premultiplied by alpha so transparent pixels do not bleed
+	let premultiplied = |pixel: &[u8]| {
+		let mut values = components(channel, pixel);
+		if let Some(alpha) = alpha {
+			let factor = values[alpha] / 255.;
+			for (i, value) in values.iter_mut().enumerate().take(count) {
+				if i != alpha {
+					*value *= factor;
+				}
+			}
+		}
+		values
+	};
+
+	Stencil::from_fn(bounds, channel, |x, y, out| {
+		let center = match (fetch(x, y), alpha) {
+			(Some(pixel), _) => premultiplied(pixel),
+			(None, Some(_)) => [0f32; 7],
+			(None, None) => return false,
+		};
+
+		let mut results = [[0f32; 7]; 2];
+		for (kernel, sums) in kernels.iter().zip(results.iter_mut()) {
+			for (dx, dy, weight) in kernel.taps() {
+				let (tx, ty) = (x + dx, y + dy);
+				let inside = tx >= bounds.x
+					&& tx < bounds.x + bounds.w
+					&& ty >= bounds.y
+					&& ty < bounds.y + bounds.h;
+				let tap = if inside || addressing != Addressing::Border {
+					fetch(
+						addressing.resolve(tx, bounds.x, bounds.w),
+						addressing.resolve(ty, bounds.y, bounds.h),
+					)
+				} else {
+					None
+				};
+				let values = match (tap, alpha) {
+					(Some(pixel), _) => premultiplied(pixel),
+					(None, Some(_)) => [0f32; 7],
+					(None, None) => center,
+				};
+				for (sum, value) in sums.iter_mut().zip(values.iter()).take(count) {
+					*sum += value * weight;
+				}
+			}
+		}
+
+		let mut values = [0f32; 7];
+		if kernels.len() > 1 {
+			for (i, value) in values.iter_mut().enumerate().take(count) {
+				*value = results
+					.iter()
+					.take(kernels.len())
+					.map(|sums| sums[i] * sums[i])
+					.sum::<f32>()
+					.sqrt();
+			}
+			// Edges stay visible around the shape as well as inside it
+			if let Some(alpha) = alpha {
+				values[alpha] = values[alpha].max(center[alpha]);
+			}
+		} else {
+			values = results[0];
+			if let Some(alpha) = alpha {
+				let factor = values[alpha].clamp(0., 255.) / 255.;
+				for (i, value) in values.iter_mut().enumerate().take(count) {
+					if i != alpha {
+						*value = if factor > 0. { *value / factor } else { 0. };
+					}
+				}
+			}
+		}
+
+		write_components(channel, &values, out);
+		match alpha {
+			Some(alpha) => out[alpha] > 0,
+			None => true,
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn kernels() {
+		let sum: f32 = Kernel::gaussian_blur(2).weights().iter().sum();
+		assert!((sum - 1.).abs() < 1e-5);
+		let gaussian = Kernel::gaussian_blur(1);
+		assert!(gaussian.weights()[4] > gaussian.weights()[1]);
+		assert!(gaussian.weights()[1] > gaussian.weights()[0]);
+		assert_eq!(Kernel::box_blur(1).weights(), &[1. / 9.; 9][..]);
+		assert_eq!(
+			Kernel::new(3, 1, vec![1., 2., 1.]).normalized().weights(),
+			&[0.25, 0.5, 0.25][..]
+		);
+	}
+
+	#[test]
+	fn stencil_convolve() {
+		let a = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 3),
+			Channel::Luma,
+			vec![0, 0, 0, 0, 90, 0, 0, 0, 0],
+		);
+		let b = a.box_blur(1);
+		assert_eq!(b.bounds(), a.bounds());
+		assert_eq!(*b.data(), vec![10, 10, 10, 10, 10, 10, 10, 10, 10]);
+
+		// Identity and sharpen keep flat areas intact
+		let a = Stencil::from_buffer(Rect::new(0, 0, 2, 2), Channel::Rgb, vec![50; 12]);
+		assert_eq!(a.sharpen().data(), a.data());
+		let identity = Kernel::new(3, 3, vec![0., 0., 0., 0., 1., 0., 0., 0., 0.]);
+		assert_eq!(a.convolve(&identity, Addressing::Border).data(), a.data());
+	}
+
+	#[test]
+	fn stencil_convolve_alpha() {
+		// Transparent neighbors lower alpha but never darken the color
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 3, 1),
+			Channel::Lumaa,
+			vec![200, 255, 0, 0, 0, 0],
+		);
+		let b = a.convolve(
+			&Kernel::new(3, 1, vec![1., 1., 1.]).normalized(),
+			Addressing::Border,
+		);
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠉⠀ )");
+		assert_eq!(*b.data(), vec![200, 85, 200, 85]);
+
+		// Masked out pixels in opaque channels are left alone
+		let a = Stencil::from_buffer(Rect::new(0, 0, 2, 1), Channel::Luma, vec![10, 30]);
+		let b = Stencil::merge(
+			&a,
+			&Stencil::from_buffer_mask_alpha(Rect::new(3, 0, 1, 1), Channel::Luma, vec![70]),
+			color::Blending::Normal,
+			color::Compositing::Lighter,
+		);
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠉⠈ )");
+		let c = b.box_blur(1);
+		assert_eq!(format!("{:?}", c), "Stencil ( ⠉⠈ )");
+		assert_eq!(*c.data(), vec![12, 28, 70]);
+	}
+
+	#[test]
+	fn stencil_sobel() {
+		let a = Stencil::from_buffer(Rect::new(0, 0, 4, 1), Channel::Luma, vec![0, 0, 100, 100]);
+		let b = a.sobel(Addressing::Clamp);
+		assert_eq!(*b.data(), vec![0, 255, 255, 0]);
+
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 3, 1),
+			Channel::Lumaa,
+			vec![0, 0, 255, 255, 0, 0],
+		);
+		let b = a.sobel(Addressing::Border);
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠉⠁ )");
+		assert_eq!(*b.data(), vec![255, 255, 0, 255, 255, 255]);
+	}
+
+	#[test]
+	fn canvas_convolve() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(-1, -1, 3, 3),
+			Channel::Luma,
+			vec![0, 0, 0, 0, 90, 0, 0, 0, 0],
+		));
+		let blurred = canvas.box_blur(1);
+		assert_eq!(blurred.bounds(), canvas.bounds());
+		let pixels: Vec<_> = blurred.iter().flatten().copied().collect();
+		assert_eq!(pixels, vec![10; 9]);
+	}
+}
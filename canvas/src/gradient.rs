@@ -0,0 +1,412 @@
+use crate::{read_color, Canvas, Stencil};
+use color::{Alpha, Channel, ChannelError, Color, Luma, Lumaa, PixelMut, Rgb, Rgba};
+use std::sync::Arc;
+use vek::{geom::repr_c::Rect, ops::Lerp, vec::repr_c::vec2::Vec2};
+
+/// 4x4 Bayer threshold matrix
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Colors placed along a 0 to 1 range
+///
+/// A ramp without stops samples transparent black.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorRamp {
+	stops: Vec<(f32, Rgba)>,
+}
+
+impl ColorRamp {
+	/// Create a ramp from `(position, color)` stops, in any order
+	///
+	/// Stops at a position that is not finite are dropped.
+	pub fn new(mut stops: Vec<(f32, Rgba)>) -> Self {
+		stops.retain(|(position, _)| position.is_finite());
+		stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+		ColorRamp { stops }
+	}
+
+	/// Create a ramp spreading colors evenly, such as a slice of a palette
+	///
+	/// Without colors, the ramp is empty.
+	pub fn from_colors(colors: &[Rgba]) -> Self {
+		let step = 1. / (colors.len().max(2) - 1) as f32;
+		ColorRamp::new(
+			colors
+				.iter()
+				.enumerate()
+				.map(|(i, color)| (i as f32 * step, *color))
+				.collect(),
+		)
+	}
+
+	/// Retrieve stops, sorted by position
+	pub fn stops(&self) -> &[(f32, Rgba)] {
+		&self.stops
+	}
+
+	/// Interpolate the color at `t`, extending the end stops past the range
+	///
+	/// A `t` that is not a number samples the first stop.
+	pub fn sample(&self, t: f32) -> Rgba {
+		let (first, last) = match (self.stops.first(), self.stops.last()) {
+			(Some(first), Some(last)) => (*first, *last),
+			_ => return Alpha::new(Rgb::new(0, 0, 0), 0),
+		};
+		if t.is_nan() || t <= first.0 {
+			return first.1;
+		}
+		if t >= last.0 {
+			return last.1;
+		}
+		let next = self.stops.iter().position(|(p, _)| *p > t).unwrap();
+		let (from, to) = (self.stops[next - 1], self.stops[next]);
+		Lerp::lerp(from.1, to.1, (t - from.0) / (to.0 - from.0))
+	}
+
+	/// Color of the stop closest to `t`
+	pub fn nearest(&self, t: f32) -> Rgba {
+		self.stops
+			.iter()
+			.min_by(|a, b| (a.0 - t).abs().total_cmp(&(b.0 - t).abs()))
+			.map(|(_, color)| *color)
+			.unwrap_or_else(|| Alpha::new(Rgb::new(0, 0, 0), 0))
+	}
+}
+
+impl Default for ColorRamp {
+	fn default() -> Self {
+		ColorRamp::new(vec![
+			(0., Alpha::new(Rgb::new(0, 0, 0), 255)),
+			(1., Alpha::new(Rgb::new(255, 255, 255), 255)),
+		])
+	}
+}
+
+/// Geometry mapping a point in document space to a ramp position
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GradientShape {
+	/// Along the segment between two points
+	Linear { from: Vec2<f32>, to: Vec2<f32> },
+	/// Outward from a center, reaching the end of the ramp at radius
+	Radial { center: Vec2<f32>, radius: f32 },
+	/// Clockwise around a center, starting at angle in radians
+	Angular { center: Vec2<f32>, angle: f32 },
+}
+
+impl GradientShape {
+	/// Ramp position of a point
+	pub fn position(&self, point: Vec2<f32>) -> f32 {
+		match *self {
+			GradientShape::Linear { from, to } => {
+				let axis = to - from;
+				let length = axis.dot(axis);
+				if length <= f32::EPSILON {
+					0.
+				} else {
+					((point - from).dot(axis) / length).clamp(0., 1.)
+				}
+			}
+			GradientShape::Radial { center, radius } => {
+				if radius <= f32::EPSILON {
+					1.
+				} else {
+					(point.distance(center) / radius).clamp(0., 1.)
+				}
+			}
+			GradientShape::Angular { center, angle } => {
+				let d = point - center;
+				let turn = (d.y.atan2(d.x) - angle) / std::f32::consts::TAU;
+				turn.rem_euclid(1.)
+			}
+		}
+	}
+}
+
+/// Gradient fill, optionally dithered to a palette
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+	pub shape: GradientShape,
+	pub ramp: ColorRamp,
+	/// Colors to restrict the fill to with ordered dithering
+	pub palette: Option<Vec<Rgba>>,
+}
+
+impl Gradient {
+	pub fn new(shape: GradientShape, ramp: ColorRamp) -> Self {
+		Gradient {
+			shape,
+			ramp,
+			palette: None,
+		}
+	}
+
+	/// Color of the pixel at `x`, `y`
+	pub fn color(&self, x: i32, y: i32) -> Rgba {
+		let color = self.ramp.sample(
+			self.shape
+				.position(Vec2::new(x as f32 + 0.5, y as f32 + 0.5)),
+		);
+		match &self.palette {
+			Some(palette) if !palette.is_empty() => {
+				// Nudge by the threshold, scaled to the average palette step
+				let threshold = (BAYER[y.rem_euclid(4) as usize][x.rem_euclid(4) as usize] as f32
+					+ 0.5) / 16. - 0.5;
+				let spread = 255. / (palette.len().max(2) - 1) as f32;
+				let nudged = [
+					color.color.red as f32 + threshold * spread,
+					color.color.green as f32 + threshold * spread,
+					color.color.blue as f32 + threshold * spread,
+					color.alpha as f32,
+				];
+				*palette
+					.iter()
+					.min_by(|a, b| {
+						distance(&nudged, a)
+							.partial_cmp(&distance(&nudged, b))
+							.unwrap()
+					})
+					.unwrap()
+			}
+			_ => color,
+		}
+	}
+
+	/// Fill every pixel of region
+	pub fn fill(&self, region: Rect<i32, i32>, channel: Channel) -> Result<Stencil, ChannelError> {
+		check_channel(channel)?;
+		Ok(Stencil::from_fn(region, channel, |x, y, pixel| {
+			write_color(channel, self.color(x, y), pixel);
+			true
+		}))
+	}
+
+	/// Fill the pixels masked in by selection, in its channel
+	pub fn fill_selection(&self, selection: &Stencil) -> Result<Stencil, ChannelError> {
+		let channel = selection.channel();
+		check_channel(channel)?;
+		Ok(Stencil::from_fn(
+			selection.bounds(),
+			channel,
+			|x, y, pixel| {
+				if selection.try_get(x, y).is_some() {
+					write_color(channel, self.color(x, y), pixel);
+					true
+				} else {
+					false
+				}
+			},
+		))
+	}
+}
+
+fn distance(a: &[f32; 4], b: &Rgba) -> f32 {
+	let b = [
+		b.color.red as f32,
+		b.color.green as f32,
+		b.color.blue as f32,
+		b.alpha as f32,
+	];
+	a.iter().zip(b.iter()).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// Recolor by luminance
+pub trait GradientMappable {
+	type Output;
+
+	/// Map the luminance of every pixel through a ramp into `Rgba`
+	///
+	/// Dark pixels take the start of the ramp and bright ones its end. When
+	/// quantized, pixels take the closest stop color instead of blending. The
+	/// alpha of the ramp is scaled by the alpha of the pixel.
+	fn gradient_map(&self, ramp: &ColorRamp, quantize: bool) -> Result<Self::Output, ChannelError>;
+}
+
+impl GradientMappable for Stencil {
+	type Output = Stencil;
+
+	fn gradient_map(&self, ramp: &ColorRamp, quantize: bool) -> Result<Self::Output, ChannelError> {
+		let channel = self.channel();
+		check_channel(channel)?;
+		Ok(Stencil::from_fn(
+			self.bounds(),
+			Channel::Rgba,
+			|x, y, out| {
+				let source = match self.try_get(x, y) {
+					Some(pixel) => read_color(channel, pixel),
+					None => return false,
+				};
+				let t = Luma::from(source.color).luma as f32 / 255.;
+				let mut color = if quantize {
+					ramp.nearest(t)
+				} else {
+					ramp.sample(t)
+				};
+				color.alpha = (color.alpha as u32 * source.alpha as u32 / 255) as u8;
+				out.copy_from_slice(color.to_slice());
+				true
+			},
+		))
+	}
+}
+
+impl GradientMappable for Canvas {
+	type Output = Canvas;
+
+	fn gradient_map(&self, ramp: &ColorRamp, quantize: bool) -> Result<Self::Output, ChannelError> {
+		let tiles = self
+			.tiles()
+			.values()
+			.map(|tile| tile.gradient_map(ramp, quantize).map(Arc::new))
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(unsafe { Canvas::from_raw_parts(Channel::Rgba, tiles) })
+	}
+}
+
+/// Fail for channels without color components
+pub(crate) fn check_channel(channel: Channel) -> Result<(), ChannelError> {
+	match channel {
+		Channel::Uv | Channel::Normal => Err(ChannelError::Mismatch(channel, Channel::Rgba)),
+		_ => Ok(()),
+	}
+}
+
+/// Write a color into the color components of a pixel
+pub(crate) fn write_color(channel: Channel, color: Rgba, out: &mut [u8]) {
+	let mut pixel = PixelMut::from_buffer_mut(out, channel);
+	match channel {
+		Channel::Luma | Channel::LumaNormal => *pixel.luma().unwrap() = Luma::from(color.color),
+		Channel::Lumaa | Channel::LumaaNormal => *pixel.lumaa().unwrap() = Lumaa::from(color),
+		Channel::Rgb | Channel::RgbNormal => *pixel.rgb().unwrap() = color.color,
+		Channel::Rgba | Channel::RgbaNormal => *pixel.rgba().unwrap() = color,
+		Channel::Uv | Channel::Normal => {}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn gray(value: u8) -> Rgba {
+		Alpha::new(Rgb::new(value, value, value), 255)
+	}
+
+	#[test]
+	fn ramp_sample() {
+		let ramp = ColorRamp::new(vec![(1., gray(200)), (0., gray(0)), (0.5, gray(100))]);
+		assert_eq!(ramp.stops()[1].1, gray(100));
+		assert_eq!(ramp.sample(-1.), gray(0));
+		assert_eq!(ramp.sample(0.25), gray(50));
+		assert_eq!(ramp.sample(0.75), gray(150));
+		assert_eq!(ramp.sample(2.), gray(200));
+		assert_eq!(ramp.sample(f32::NAN), gray(0));
+
+		let ramp = ColorRamp::new(vec![(f32::NAN, gray(50)), (1., gray(200)), (0., gray(0))]);
+		assert_eq!(ramp.stops().len(), 2);
+		assert_eq!(ramp.sample(0.5), gray(100));
+		let empty = ColorRamp::new(vec![]);
+		assert_eq!(empty.sample(0.5), Alpha::new(Rgb::new(0, 0, 0), 0));
+	}
+
+	#[test]
+	fn shapes() {
+		let linear = GradientShape::Linear {
+			from: Vec2::new(0., 0.),
+			to: Vec2::new(4., 0.),
+		};
+		assert_eq!(linear.position(Vec2::new(1., 7.)), 0.25);
+		assert_eq!(linear.position(Vec2::new(-1., 0.)), 0.);
+		let radial = GradientShape::Radial {
+			center: Vec2::new(0., 0.),
+			radius: 10.,
+		};
+		assert_eq!(radial.position(Vec2::new(3., 4.)), 0.5);
+		let angular = GradientShape::Angular {
+			center: Vec2::new(0., 0.),
+			angle: 0.,
+		};
+		assert_eq!(angular.position(Vec2::new(1., 0.)), 0.);
+		assert_eq!(angular.position(Vec2::new(0., 1.)), 0.25);
+		assert_eq!(angular.position(Vec2::new(-1., 0.)), 0.5);
+	}
+
+	#[test]
+	fn fill() {
+		let gradient = Gradient::new(
+			GradientShape::Linear {
+				from: Vec2::new(0., 0.),
+				to: Vec2::new(4., 0.),
+			},
+			ColorRamp::default(),
+		);
+		let a = gradient.fill(Rect::new(0, 0, 4, 1), Channel::Luma).unwrap();
+		assert_eq!(*a.data(), vec![31, 95, 159, 223]);
+		let a = gradient.fill(Rect::new(0, 0, 1, 1), Channel::Rgba).unwrap();
+		assert_eq!(*a.data(), vec![31, 31, 31, 255]);
+		assert!(gradient.fill(Rect::new(0, 0, 1, 1), Channel::Uv).is_err());
+
+		let selection = Stencil::from_buffer_mask_alpha(
+			Rect::new(1, 0, 2, 1),
+			Channel::Lumaa,
+			vec![0, 0, 9, 255],
+		);
+		let a = gradient.fill_selection(&selection).unwrap();
+		assert_eq!(a.try_get(1, 0), None);
+		assert_eq!(a.try_get(2, 0), Some(&[159, 255][..]));
+	}
+
+	#[test]
+	fn dithering() {
+		let mut gradient = Gradient::new(
+			GradientShape::Linear {
+				from: Vec2::new(0., 0.),
+				to: Vec2::new(4., 0.),
+			},
+			ColorRamp::new(vec![(0., gray(128)), (1., gray(128))]),
+		);
+		gradient.palette = Some(vec![gray(0), gray(255)]);
+		let a = gradient.fill(Rect::new(0, 0, 4, 4), Channel::Luma).unwrap();
+		// A flat mid gray becomes an even checker of both colors
+		assert_eq!(a.data().iter().filter(|v| **v == 255).count(), 8);
+		assert_eq!(a.data().iter().filter(|v| **v == 0).count(), 8);
+		assert_eq!(a.try_get(0, 0), Some(&[0][..]));
+		assert_eq!(a.try_get(1, 0), Some(&[255][..]));
+	}
+
+	#[test]
+	fn ramp_from_colors() {
+		let ramp = ColorRamp::from_colors(&[gray(0), gray(100), gray(200)]);
+		assert_eq!(ramp.stops()[1], (0.5, gray(100)));
+		assert_eq!(ramp.nearest(0.3), gray(100));
+		assert_eq!(ramp.nearest(0.2), gray(0));
+		assert_eq!(ColorRamp::from_colors(&[gray(7)]).sample(0.5), gray(7));
+
+		let empty = ColorRamp::from_colors(&[]);
+		assert!(empty.stops().is_empty());
+		assert_eq!(empty.nearest(0.5), Alpha::new(Rgb::new(0, 0, 0), 0));
+		assert_eq!(ramp.nearest(f32::NAN), gray(0));
+	}
+
+	#[test]
+	fn gradient_map() {
+		let ramp = ColorRamp::from_colors(&[
+			Alpha::new(Rgb::new(0, 0, 100), 255),
+			Alpha::new(Rgb::new(200, 100, 0), 255),
+		]);
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 3, 1),
+			Channel::Lumaa,
+			vec![0, 255, 255, 128, 51, 255],
+		);
+		let b = a.gradient_map(&ramp, false).unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		assert_eq!(b.try_get(0, 0), Some(&[0, 0, 100, 255][..]));
+		assert_eq!(b.try_get(1, 0), Some(&[200, 100, 0, 128][..]));
+		assert_eq!(b.try_get(2, 0), Some(&[40, 20, 80, 255][..]));
+
+		let b = Canvas::from_stencil(a).gradient_map(&ramp, true).unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		assert_eq!(b[(2, 0)], [0, 0, 100, 255]);
+
+		let uv = Stencil::new(Rect::new(0, 0, 1, 1), Channel::Uv);
+		assert!(uv.gradient_map(&ramp, false).is_err());
+	}
+}
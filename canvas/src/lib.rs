@@ -1,10 +1,36 @@
+mod adjust;
 mod braille;
 mod canvas;
+mod filter;
+mod gradient;
+mod lighting;
+mod noise;
+mod normal;
+mod orient;
+mod outline;
+mod replace;
+mod resize;
 mod sampling;
 mod stencil;
+mod text;
 mod transform;
+mod uv;
+mod vector;
 
+pub use self::adjust::*;
 pub use self::canvas::*;
+pub use self::filter::*;
+pub use self::gradient::*;
+pub use self::lighting::*;
+pub use self::noise::*;
+pub use self::normal::*;
+pub use self::orient::*;
+pub use self::outline::*;
+pub use self::replace::*;
+pub use self::resize::*;
 pub use self::sampling::*;
 pub use self::stencil::*;
+pub use self::text::*;
 pub use self::transform::*;
+pub use self::uv::*;
+pub use self::vector::*;
@@ -0,0 +1,230 @@
+use crate::{component_layout, components, Canvas, Stencil};
+use color::{Channel, ChannelError, Rgb};
+use std::sync::Arc;
+use vek::vec::repr_c::vec3::Vec3;
+
+/// Light source, in document space with z pointing toward the viewer
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Light {
+	/// Light spreading from a position, fading out at radius if positive
+	Point {
+		position: Vec3<f32>,
+		color: Rgb,
+		intensity: f32,
+		radius: f32,
+	},
+	/// Parallel light traveling along direction
+	Directional {
+		direction: Vec3<f32>,
+		color: Rgb,
+		intensity: f32,
+	},
+}
+
+impl Light {
+	/// Diffuse contribution at a point with a unit normal, per color component
+	pub fn diffuse(&self, point: Vec3<f32>, normal: Vec3<f32>) -> Vec3<f32> {
+		let (to_light, color, intensity) = match *self {
+			Light::Point {
+				position,
+				color,
+				intensity,
+				radius,
+			} => {
+				let offset = position - point;
+				let distance = offset.magnitude();
+				let falloff = if radius > 0. {
+					(1. - distance / radius).max(0.).powi(2)
+				} else {
+					1.
+				};
+				(offset.normalized(), color, intensity * falloff)
+			}
+			Light::Directional {
+				direction,
+				color,
+				intensity,
+			} => (-direction.normalized(), color, intensity),
+		};
+		let lambert = normal.dot(to_light).max(0.);
+		if !lambert.is_finite() {
+			return Vec3::zero();
+		}
+		Vec3::new(color.red as f32, color.green as f32, color.blue as f32) / 255.
+			* lambert * intensity
+	}
+}
+
+/// Lights applied to a normal mapped image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lighting {
+	/// Light reaching every pixel regardless of its normal
+	pub ambient: Rgb,
+	pub lights: Vec<Light>,
+}
+
+impl Default for Lighting {
+	fn default() -> Self {
+		Lighting {
+			ambient: Rgb::new(32, 32, 32),
+			lights: vec![],
+		}
+	}
+}
+
+impl Lighting {
+	/// Light a single pixel of `RgbNormal` or `RgbaNormal` data into `Rgba`
+	fn shade(&self, channel: Channel, x: i32, y: i32, pixel: &[u8], out: &mut [u8]) {
+		let (bytes, _) = component_layout(channel);
+		let values = components(channel, pixel);
+		let normal = Vec3::new(values[bytes], values[bytes + 1], values[bytes + 2]);
+		let normal = if normal.magnitude_squared() > f32::EPSILON {
+			normal.normalized()
+		} else {
+			Vec3::unit_z()
+		};
+		let point = Vec3::new(x as f32 + 0.5, y as f32 + 0.5, 0.);
+		let light = self.lights.iter().fold(
+			Vec3::new(
+				self.ambient.red as f32,
+				self.ambient.green as f32,
+				self.ambient.blue as f32,
+			) / 255.,
+			|sum, light| sum + light.diffuse(point, normal),
+		);
+		for i in 0..3 {
+			out[i] = (values[i] * light[i]).round().clamp(0., 255.) as u8;
+		}
+		out[3] = if bytes == 4 { values[3] as u8 } else { 255 };
+	}
+}
+
+/// Preview of normal mapped colors under lights
+pub trait Lightable {
+	type Output;
+
+	/// Render `RgbNormal` or `RgbaNormal` pixels lit by lighting into `Rgba`
+	fn light(&self, lighting: &Lighting) -> Result<Self::Output, ChannelError>;
+}
+
+impl Lightable for Stencil {
+	type Output = Stencil;
+
+	fn light(&self, lighting: &Lighting) -> Result<Self::Output, ChannelError> {
+		let channel = self.channel();
+		match channel {
+			Channel::RgbNormal | Channel::RgbaNormal => {}
+			_ => return Err(ChannelError::Mismatch(channel, Channel::RgbaNormal)),
+		}
+		Ok(Stencil::from_fn(
+			self.bounds(),
+			Channel::Rgba,
+			|x, y, out| match self.try_get(x, y) {
+				Some(pixel) => {
+					lighting.shade(channel, x, y, pixel, out);
+					true
+				}
+				None => false,
+			},
+		))
+	}
+}
+
+impl Lightable for Canvas {
+	type Output = Canvas;
+
+	fn light(&self, lighting: &Lighting) -> Result<Self::Output, ChannelError> {
+		match self.channel() {
+			Channel::RgbNormal | Channel::RgbaNormal => {}
+			channel => return Err(ChannelError::Mismatch(channel, Channel::RgbaNormal)),
+		}
+		let tiles = self
+			.tiles()
+			.values()
+			.map(|tile| tile.light(lighting).map(Arc::new))
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(unsafe { Canvas::from_raw_parts(Channel::Rgba, tiles) })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::write_components;
+	use vek::geom::repr_c::Rect;
+
+	fn pixel(normal: Vec3<f32>) -> Vec<u8> {
+		let mut out = vec![0u8; Channel::RgbaNormal.pixel_stride()];
+		write_components(
+			Channel::RgbaNormal,
+			&[200., 100., 50., 128., normal.x, normal.y, normal.z],
+			&mut out,
+		);
+		out
+	}
+
+	#[test]
+	fn directional() {
+		let a = Stencil::from_buffer(
+			Rect::new(0, 0, 2, 1),
+			Channel::RgbaNormal,
+			[pixel(Vec3::unit_z()), pixel(Vec3::unit_x())].concat(),
+		);
+		let lighting = Lighting {
+			ambient: Rgb::new(0, 0, 0),
+			lights: vec![Light::Directional {
+				direction: Vec3::new(0., 0., -1.),
+				color: Rgb::new(255, 255, 255),
+				intensity: 1.,
+			}],
+		};
+		let b = a.light(&lighting).unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		// Facing the light, then perpendicular to it
+		assert_eq!(b.try_get(0, 0), Some(&[200, 100, 50, 128][..]));
+		assert_eq!(b.try_get(1, 0), Some(&[0, 0, 0, 128][..]));
+
+		let b = a.light(&Lighting::default()).unwrap();
+		assert_eq!(b.try_get(0, 0), Some(&[25, 13, 6, 128][..]));
+
+		let plain = Stencil::new(Rect::new(0, 0, 1, 1), Channel::Rgba);
+		assert!(plain.light(&lighting).is_err());
+	}
+
+	#[test]
+	fn point() {
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 3, 1),
+			Channel::RgbaNormal,
+			[
+				pixel(Vec3::unit_z()),
+				pixel(Vec3::unit_z()),
+				pixel(Vec3::unit_z()),
+			]
+			.concat(),
+		));
+		let lighting = Lighting {
+			ambient: Rgb::new(0, 0, 0),
+			lights: vec![Light::Point {
+				position: Vec3::new(0.5, 0.5, 1.),
+				color: Rgb::new(255, 0, 0),
+				intensity: 1.,
+				radius: 2.,
+			}],
+		};
+		let b = a.light(&lighting).unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		assert_eq!(b[(0, 0)], [50, 0, 0, 128]);
+		assert_eq!(b[(2, 0)], [0, 0, 0, 128]);
+		assert!(b[(1, 0)][0] > 0 && b[(1, 0)][0] < 50);
+	}
+
+	#[test]
+	fn empty_canvas() {
+		let lighting = Lighting::default();
+		assert!(Canvas::new(Channel::Rgba).light(&lighting).is_err());
+		let b = Canvas::new(Channel::RgbNormal).light(&lighting).unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		assert!(b.tiles().is_empty());
+	}
+}
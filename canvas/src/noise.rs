@@ -0,0 +1,286 @@
+use crate::{check_channel, write_color, ColorRamp, Stencil};
+use color::{Channel, ChannelError};
+use std::convert::TryFrom;
+use vek::{geom::repr_c::Rect, vec::repr_c::vec2::Vec2};
+
+/// Procedural noise functions
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NoiseKind {
+	/// Smoothly interpolated random values on a square lattice
+	Value,
+	/// Gradient noise on a square lattice
+	Perlin,
+	/// Gradient noise on a triangular lattice, made tileable by blending
+	/// shifted copies, which softens its contrast
+	Simplex,
+	/// Distance to the closest random feature point, one per cell
+	Cellular,
+}
+
+/// Seeded noise that tiles across the region it fills
+#[derive(Debug, Clone, PartialEq)]
+pub struct Noise {
+	pub kind: NoiseKind,
+	pub seed: u64,
+	/// Lattice cells across the region, horizontally and vertically
+	pub cells: Vec2<u32>,
+	/// Layers of detail, each with twice the cells and half the weight
+	pub octaves: u32,
+	pub ramp: ColorRamp,
+}
+
+impl Noise {
+	pub fn new(kind: NoiseKind, seed: u64) -> Self {
+		Noise {
+			kind,
+			seed,
+			cells: Vec2::new(4, 4),
+			octaves: 1,
+			ramp: ColorRamp::default(),
+		}
+	}
+
+	/// Noise in 0..1 at `u`, `v` fractions of the region, repeating every unit
+	pub fn value_at(&self, u: f32, v: f32) -> f32 {
+		let mut sum = 0.;
+		let mut total = 0.;
+		let mut weight = 1.;
+		for octave in 0..self.octaves.max(1) {
+			// Octaves past a representable lattice would add next to nothing
+			let period = match (
+				octave_cells(self.cells.x, octave),
+				octave_cells(self.cells.y, octave),
+			) {
+				(Some(x), Some(y)) => Vec2::new(x, y),
+				_ => break,
+			};
+			let point = Vec2::new(
+				u.rem_euclid(1.) * period.x as f32,
+				v.rem_euclid(1.) * period.y as f32,
+			);
+			let seed = self.seed.wrapping_add(octave as u64);
+			let value = match self.kind {
+				NoiseKind::Value => value(seed, point, period),
+				NoiseKind::Perlin => perlin(seed, point, period),
+				NoiseKind::Simplex => tiled_simplex(seed, point, period),
+				NoiseKind::Cellular => cellular(seed, point, period),
+			};
+			sum += value * weight;
+			total += weight;
+			weight *= 0.5;
+		}
+		(sum / total).clamp(0., 1.)
+	}
+
+	/// Fill region with noise mapped through the ramp
+	pub fn fill(&self, region: Rect<i32, i32>, channel: Channel) -> Result<Stencil, ChannelError> {
+		check_channel(channel)?;
+		Ok(Stencil::from_fn(region, channel, |x, y, pixel| {
+			let u = (x - region.x) as f32 / region.w as f32;
+			let v = (y - region.y) as f32 / region.h as f32;
+			write_color(channel, self.ramp.sample(self.value_at(u, v)), pixel);
+			true
+		}))
+	}
+}
+
+/// Cells across the region at an octave, if they fit in an `i32`
+fn octave_cells(cells: u32, octave: u32) -> Option<i32> {
+	(cells.max(1) as u64)
+		.checked_shl(octave)
+		.and_then(|cells| i32::try_from(cells).ok())
+}
+
+/// Hash a lattice point into 32 random bits
+fn hash(seed: u64, x: i32, y: i32) -> u32 {
+	let mut h = seed
+		.wrapping_add((x as u32 as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+		.wrapping_add((y as u32 as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f));
+	h ^= h >> 30;
+	h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+	h ^= h >> 27;
+	h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+	h ^= h >> 31;
+	h as u32
+}
+
+/// Hash a lattice point wrapped to period into 0..1
+fn random(seed: u64, x: i32, y: i32, period: Vec2<i32>) -> f32 {
+	hash(seed, x.rem_euclid(period.x), y.rem_euclid(period.y)) as f32 / u32::MAX as f32
+}
+
+/// Unit gradient of a lattice point wrapped to period
+fn gradient(seed: u64, x: i32, y: i32, period: Vec2<i32>) -> Vec2<f32> {
+	let angle = random(seed, x, y, period) * std::f32::consts::TAU;
+	Vec2::new(angle.cos(), angle.sin())
+}
+
+/// Quintic ease with zero first and second derivatives at 0 and 1
+fn fade(t: f32) -> f32 {
+	t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+	a + (b - a) * t
+}
+
+fn value(seed: u64, point: Vec2<f32>, period: Vec2<i32>) -> f32 {
+	let (x0, y0) = (point.x.floor() as i32, point.y.floor() as i32);
+	let (tx, ty) = (fade(point.x - x0 as f32), fade(point.y - y0 as f32));
+	let top = lerp(
+		random(seed, x0, y0, period),
+		random(seed, x0 + 1, y0, period),
+		tx,
+	);
+	let bottom = lerp(
+		random(seed, x0, y0 + 1, period),
+		random(seed, x0 + 1, y0 + 1, period),
+		tx,
+	);
+	lerp(top, bottom, ty)
+}
+
+fn perlin(seed: u64, point: Vec2<f32>, period: Vec2<i32>) -> f32 {
+	let (x0, y0) = (point.x.floor() as i32, point.y.floor() as i32);
+	let (fx, fy) = (point.x - x0 as f32, point.y - y0 as f32);
+	let dot = |dx: i32, dy: i32| {
+		gradient(seed, x0 + dx, y0 + dy, period).dot(Vec2::new(fx - dx as f32, fy - dy as f32))
+	};
+	let (tx, ty) = (fade(fx), fade(fy));
+	let n = lerp(
+		lerp(dot(0, 0), dot(1, 0), tx),
+		lerp(dot(0, 1), dot(1, 1), tx),
+		ty,
+	);
+	// Unit gradients keep 2D Perlin noise within half a diagonal of zero
+	(n * std::f32::consts::SQRT_2 + 1.) / 2.
+}
+
+fn simplex(seed: u64, point: Vec2<f32>) -> f32 {
+	const F: f32 = 0.366_025_42; // (sqrt(3) - 1) / 2
+	const G: f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+	let no_wrap = Vec2::new(i32::MAX, i32::MAX);
+
+	let s = (point.x + point.y) * F;
+	let (i, j) = ((point.x + s).floor() as i32, (point.y + s).floor() as i32);
+	let t = (i + j) as f32 * G;
+	let d0 = Vec2::new(point.x - (i as f32 - t), point.y - (j as f32 - t));
+	let (oi, oj) = if d0.x > d0.y { (1, 0) } else { (0, 1) };
+	let corners = [
+		(i, j, d0),
+		(
+			i + oi,
+			j + oj,
+			d0 - Vec2::new(oi as f32, oj as f32) + Vec2::broadcast(G),
+		),
+		(i + 1, j + 1, d0 - Vec2::broadcast(1. - 2. * G)),
+	];
+	let n: f32 = corners
+		.iter()
+		.map(|(ci, cj, d)| {
+			let falloff = 0.5 - d.dot(*d);
+			if falloff <= 0. {
+				0.
+			} else {
+				falloff.powi(4) * gradient(seed, *ci, *cj, no_wrap).dot(*d)
+			}
+		})
+		.sum();
+	(n * 70. + 1.) / 2.
+}
+
+/// Blend simplex noise with copies shifted by one period so the edges match
+fn tiled_simplex(seed: u64, point: Vec2<f32>, period: Vec2<i32>) -> f32 {
+	let (w, h) = (period.x as f32, period.y as f32);
+	let (fx, fy) = (point.x / w, point.y / h);
+	let sample = |dx: f32, dy: f32| simplex(seed, Vec2::new(point.x - dx, point.y - dy));
+	let top = lerp(sample(0., 0.), sample(w, 0.), fx);
+	let bottom = lerp(sample(0., h), sample(w, h), fx);
+	let n = lerp(top, bottom, fy);
+	// Blending pulls values toward the middle, stretch them back
+	((n - 0.5) * 2. + 0.5).clamp(0., 1.)
+}
+
+fn cellular(seed: u64, point: Vec2<f32>, period: Vec2<i32>) -> f32 {
+	let (x0, y0) = (point.x.floor() as i32, point.y.floor() as i32);
+	let mut closest = f32::MAX;
+	for dy in -1..=1 {
+		for dx in -1..=1 {
+			let (cx, cy) = (x0 + dx, y0 + dy);
+			let feature = Vec2::new(
+				cx as f32 + random(seed, cx, cy, period),
+				cy as f32 + random(seed ^ 0x5bd1_e995, cx, cy, period),
+			);
+			closest = closest.min(feature.distance(point));
+		}
+	}
+	closest.min(1.)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const KINDS: [NoiseKind; 4] = [
+		NoiseKind::Value,
+		NoiseKind::Perlin,
+		NoiseKind::Simplex,
+		NoiseKind::Cellular,
+	];
+
+	#[test]
+	fn deterministic() {
+		for kind in KINDS.iter() {
+			let a = Noise::new(*kind, 7)
+				.fill(Rect::new(0, 0, 16, 16), Channel::Luma)
+				.unwrap();
+			let b = Noise::new(*kind, 7)
+				.fill(Rect::new(0, 0, 16, 16), Channel::Luma)
+				.unwrap();
+			let c = Noise::new(*kind, 8)
+				.fill(Rect::new(0, 0, 16, 16), Channel::Luma)
+				.unwrap();
+			assert_eq!(a.data(), b.data());
+			assert_ne!(a.data(), c.data());
+			// Not a flat fill
+			assert!(a.data().iter().any(|v| *v != a.data()[0]));
+		}
+	}
+
+	#[test]
+	fn tileable() {
+		let e = 1e-5;
+		for kind in KINDS.iter() {
+			let mut noise = Noise::new(*kind, 3);
+			noise.octaves = 3;
+			noise.cells = Vec2::new(3, 2);
+			for i in 0..10 {
+				let t = i as f32 / 10.;
+				let a = noise.value_at(t, e);
+				assert!((0. ..=1.).contains(&a));
+				// Opposite edges meet across the seam
+				assert!((noise.value_at(t, 1. - e) - a).abs() < 5e-3);
+				assert!((noise.value_at(1. - e, t) - noise.value_at(e, t)).abs() < 5e-3);
+			}
+		}
+	}
+
+	#[test]
+	fn many_octaves() {
+		let mut noise = Noise::new(NoiseKind::Value, 3);
+		noise.octaves = 40;
+		let a = noise.value_at(0.3, 0.6);
+		noise.octaves = u32::MAX;
+		assert_eq!(noise.value_at(0.3, 0.6), a);
+	}
+
+	#[test]
+	fn fill() {
+		let noise = Noise::new(NoiseKind::Value, 1);
+		let a = noise.fill(Rect::new(5, 5, 4, 3), Channel::Rgba).unwrap();
+		assert_eq!(a.bounds(), Rect::new(5, 5, 4, 3));
+		assert_eq!(a.data().len(), 4 * 3 * 4);
+		assert!(a.data().chunks(4).all(|px| px[0] == px[1] && px[3] == 255));
+		assert!(noise.fill(Rect::new(0, 0, 1, 1), Channel::Normal).is_err());
+	}
+}
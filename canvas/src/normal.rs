@@ -0,0 +1,251 @@
+use crate::{component_layout, components, write_components, Addressing, Canvas, Kernel, Stencil};
+use color::{Channel, ChannelError};
+use vek::{geom::repr_c::Rect, vec::repr_c::vec3::Vec3};
+
+/// Channel holding the color components of `channel` along with a normal
+pub fn normal_channel(channel: Channel) -> Option<Channel> {
+	match channel {
+		Channel::Luma | Channel::LumaNormal => Some(Channel::LumaNormal),
+		Channel::Lumaa | Channel::LumaaNormal => Some(Channel::LumaaNormal),
+		Channel::Rgb | Channel::RgbNormal => Some(Channel::RgbNormal),
+		Channel::Rgba | Channel::RgbaNormal => Some(Channel::RgbaNormal),
+		Channel::Uv | Channel::Normal => None,
+	}
+}
+
+/// Normal maps for 2D lighting
+///
+/// Normals are unit vectors with x pointing right, y pointing down and z
+/// pointing toward the viewer, so a flat surface is `(0, 0, 1)`.
+pub trait NormalMappable {
+	type Output;
+
+	/// Derive a `Normal` channel from a `Luma` height map
+	///
+	/// Slopes are measured with the Sobel operators on heights scaled to 0..1
+	/// and multiplied by strength before normalizing.
+	fn normal_map(
+		&self,
+		strength: f32,
+		addressing: Addressing,
+	) -> Result<Self::Output, ChannelError>;
+
+	/// Combine colors with the normals of a `Normal` channel
+	///
+	/// The result is in the matching `*Normal` channel and keeps the pixels of
+	/// self. Pixels without a normal keep theirs, or face the viewer.
+	fn with_normals(&self, normals: &Self) -> Result<Self::Output, ChannelError>;
+}
+
+impl NormalMappable for Stencil {
+	type Output = Stencil;
+
+	fn normal_map(
+		&self,
+		strength: f32,
+		addressing: Addressing,
+	) -> Result<Self::Output, ChannelError> {
+		if self.channel() != Channel::Luma {
+			return Err(ChannelError::Mismatch(self.channel(), Channel::Luma));
+		}
+		Ok(normal_map(
+			|x, y| self.try_get(x, y),
+			self.bounds(),
+			strength,
+			addressing,
+		))
+	}
+
+	fn with_normals(&self, normals: &Self) -> Result<Self::Output, ChannelError> {
+		let channel = check_normals(self.channel(), normals.channel())?;
+		Ok(with_normals(
+			|x, y| self.try_get(x, y),
+			|x, y| normals.try_get(x, y),
+			self.bounds(),
+			self.channel(),
+			channel,
+		))
+	}
+}
+
+impl NormalMappable for Canvas {
+	type Output = Canvas;
+
+	fn normal_map(
+		&self,
+		strength: f32,
+		addressing: Addressing,
+	) -> Result<Self::Output, ChannelError> {
+		if self.channel() != Channel::Luma {
+			return Err(ChannelError::Mismatch(self.channel(), Channel::Luma));
+		}
+		Ok(Canvas::from_stencil(normal_map(
+			|x, y| self.try_get(x, y),
+			self.bounds(),
+			strength,
+			addressing,
+		)))
+	}
+
+	fn with_normals(&self, normals: &Self) -> Result<Self::Output, ChannelError> {
+		let channel = check_normals(self.channel(), normals.channel())?;
+		Ok(Canvas::from_stencil(with_normals(
+			|x, y| self.try_get(x, y),
+			|x, y| normals.try_get(x, y),
+			self.bounds(),
+			self.channel(),
+			channel,
+		)))
+	}
+}
+
+fn check_normals(channel: Channel, normals: Channel) -> Result<Channel, ChannelError> {
+	if normals != Channel::Normal {
+		return Err(ChannelError::Mismatch(normals, Channel::Normal));
+	}
+	normal_channel(channel).ok_or(ChannelError::Mismatch(channel, Channel::RgbaNormal))
+}
+
+fn normal_map<'a, F>(
+	fetch: F,
+	bounds: Rect<i32, i32>,
+	strength: f32,
+	addressing: Addressing,
+) -> Stencil
+where
+	F: Fn(i32, i32) -> Option<&'a [u8]> + Send + Sync,
+{
+	let (sobel_x, sobel_y) = (Kernel::sobel_x(), Kernel::sobel_y());
+	let taps: Vec<(i32, i32, f32, f32)> = (0..9)
+		.map(|i| {
+			(
+				i % 3 - 1,
+				i / 3 - 1,
+				sobel_x.weights()[i as usize],
+				sobel_y.weights()[i as usize],
+			)
+		})
+		.collect();
+
+	Stencil::from_fn(bounds, Channel::Normal, |x, y, out| {
+		let center = match fetch(x, y) {
+			Some(pixel) => pixel[0] as f32 / 255.,
+			None => return false,
+		};
+		let (mut dx, mut dy) = (0f32, 0f32);
+		for (ox, oy, wx, wy) in taps.iter() {
+			let (tx, ty) = (x + ox, y + oy);
+			let inside = tx >= bounds.x
+				&& tx < bounds.x + bounds.w
+				&& ty >= bounds.y
+				&& ty < bounds.y + bounds.h;
+			// Missing neighbors repeat the center height
+			let height = if inside || addressing != Addressing::Border {
+				fetch(
+					addressing.resolve(tx, bounds.x, bounds.w),
+					addressing.resolve(ty, bounds.y, bounds.h),
+				)
+				.map_or(center, |pixel| pixel[0] as f32 / 255.)
+			} else {
+				center
+			};
+			dx += height * wx;
+			dy += height * wy;
+		}
+		let normal = Vec3::new(-dx * strength, -dy * strength, 1.).normalized();
+		write_components(
+			Channel::Normal,
+			&[normal.x, normal.y, normal.z, 0., 0., 0., 0.],
+			out,
+		);
+		true
+	})
+}
+
+fn with_normals<'a, F, N>(
+	fetch: F,
+	normals: N,
+	bounds: Rect<i32, i32>,
+	from: Channel,
+	to: Channel,
+) -> Stencil
+where
+	F: Fn(i32, i32) -> Option<&'a [u8]> + Send + Sync,
+	N: Fn(i32, i32) -> Option<&'a [u8]> + Send + Sync,
+{
+	let (bytes, floats) = component_layout(from);
+	Stencil::from_fn(bounds, to, |x, y, out| {
+		let pixel = match fetch(x, y) {
+			Some(pixel) => pixel,
+			None => return false,
+		};
+		let mut values = components(from, pixel);
+		if floats == 0 {
+			values[bytes + 2] = 1.;
+		}
+		if let Some(normal) = normals(x, y) {
+			let normal = components(Channel::Normal, normal);
+			values[bytes..bytes + 3].copy_from_slice(&normal[..3]);
+		}
+		write_components(to, &values, out);
+		true
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn normal(stencil: &Stencil, x: i32, y: i32) -> Vec3<f32> {
+		let values = components(stencil.channel(), stencil.try_get(x, y).unwrap());
+		let (bytes, _) = component_layout(stencil.channel());
+		Vec3::new(values[bytes], values[bytes + 1], values[bytes + 2])
+	}
+
+	#[test]
+	fn normal_map() {
+		// A ramp rising to the right
+		let a = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 2),
+			Channel::Luma,
+			vec![0, 51, 102, 0, 51, 102],
+		);
+		let b = a.normal_map(1., Addressing::Clamp).unwrap();
+		assert_eq!(b.channel(), Channel::Normal);
+		assert_eq!(b.bounds(), a.bounds());
+		let n = normal(&b, 1, 0);
+		// Sobel sums to 8 * 0.2 across the ramp
+		let expected = Vec3::new(-1.6f32, 0., 1.).normalized();
+		assert!((n - expected).magnitude() < 1e-5);
+		assert!(normal(&b, 0, 1).x < 0.);
+
+		let flat = Stencil::from_buffer(Rect::new(0, 0, 2, 2), Channel::Luma, vec![9; 4]);
+		let n = normal(&flat.normal_map(4., Addressing::Border).unwrap(), 0, 0);
+		assert!((n - Vec3::new(0., 0., 1.)).magnitude() < 1e-5);
+
+		assert!(b.normal_map(1., Addressing::Clamp).is_err());
+	}
+
+	#[test]
+	fn with_normals() {
+		let color = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 1),
+			Channel::Rgba,
+			vec![1, 2, 3, 255, 4, 5, 6, 255],
+		);
+		let heights = Stencil::from_buffer(Rect::new(0, 0, 1, 1), Channel::Luma, vec![0]);
+		let normals = heights.normal_map(1., Addressing::Clamp).unwrap();
+		let b = color.with_normals(&normals).unwrap();
+		assert_eq!(b.channel(), Channel::RgbaNormal);
+		assert_eq!(&b.try_get(1, 0).unwrap()[..4], &[4, 5, 6, 255]);
+		assert_eq!(normal(&b, 0, 0), Vec3::new(0., 0., 1.));
+		assert_eq!(normal(&b, 1, 0), Vec3::new(0., 0., 1.));
+
+		assert!(color.with_normals(&color).is_err());
+		assert!(normals.with_normals(&normals).is_err());
+
+		let canvas = Canvas::from_stencil(color);
+		let b = canvas.with_normals(&Canvas::from_stencil(normals)).unwrap();
+		assert_eq!(b.channel(), Channel::RgbaNormal);
+	}
+}
@@ -0,0 +1,267 @@
+use crate::{Canvas, Stencil};
+use bitvec::{bitvec, order::Lsb0};
+use std::sync::Arc;
+use vek::geom::repr_c::Rect;
+
+/// Lossless orientation changes
+///
+/// Rotations are clockwise and keep the top-left corner of the bounds in place.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Orientation {
+	FlipHorizontal,
+	FlipVertical,
+	Rotate90,
+	Rotate180,
+	Rotate270,
+	Transpose,
+}
+
+impl Orientation {
+	/// Returns true if width and height are swapped
+	pub fn swaps_axes(self) -> bool {
+		matches!(
+			self,
+			Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Transpose
+		)
+	}
+
+	/// Map a local coordinate from a `w` by `h` area to its new location
+	pub fn map(self, x: i32, y: i32, w: i32, h: i32) -> (i32, i32) {
+		match self {
+			Orientation::FlipHorizontal => (w - 1 - x, y),
+			Orientation::FlipVertical => (x, h - 1 - y),
+			Orientation::Rotate90 => (h - 1 - y, x),
+			Orientation::Rotate180 => (w - 1 - x, h - 1 - y),
+			Orientation::Rotate270 => (y, w - 1 - x),
+			Orientation::Transpose => (y, x),
+		}
+	}
+
+	/// Map a rectangle within `container` to its new location
+	pub fn map_rect(self, rect: Rect<i32, i32>, container: Rect<i32, i32>) -> Rect<i32, i32> {
+		let (ax, ay) = self.map(
+			rect.x - container.x,
+			rect.y - container.y,
+			container.w,
+			container.h,
+		);
+		let (bx, by) = self.map(
+			rect.x - container.x + rect.w - 1,
+			rect.y - container.y + rect.h - 1,
+			container.w,
+			container.h,
+		);
+		let (w, h) = if self.swaps_axes() {
+			(rect.h, rect.w)
+		} else {
+			(rect.w, rect.h)
+		};
+		Rect::new(container.x + ax.min(bx), container.y + ay.min(by), w, h)
+	}
+}
+
+pub trait Orientable {
+	type Output;
+
+	/// Remap pixels to a new orientation without resampling
+	fn orient(&self, orientation: Orientation) -> Self::Output;
+
+	/// Mirror left to right
+	fn flip_horizontal(&self) -> Self::Output {
+		self.orient(Orientation::FlipHorizontal)
+	}
+
+	/// Mirror top to bottom
+	fn flip_vertical(&self) -> Self::Output {
+		self.orient(Orientation::FlipVertical)
+	}
+
+	/// Rotate a quarter turn clockwise
+	fn rotate90(&self) -> Self::Output {
+		self.orient(Orientation::Rotate90)
+	}
+
+	/// Rotate a half turn
+	fn rotate180(&self) -> Self::Output {
+		self.orient(Orientation::Rotate180)
+	}
+
+	/// Rotate a quarter turn counter-clockwise
+	fn rotate270(&self) -> Self::Output {
+		self.orient(Orientation::Rotate270)
+	}
+
+	/// Swap rows and columns
+	fn transpose(&self) -> Self::Output {
+		self.orient(Orientation::Transpose)
+	}
+}
+
+impl Orientable for Stencil {
+	type Output = Stencil;
+
+	fn orient(&self, orientation: Orientation) -> Self::Output {
+		let channel = self.channel();
+		let stride = channel.pixel_stride();
+		let bounds = self.bounds();
+		let old_mask = self.mask();
+		let old_data = self.data();
+		let new_bounds = orientation.map_rect(bounds, bounds);
+		let len = old_mask.len();
+
+		// Destination index of every source pixel, in source order
+		let mut target = vec![0usize; len];
+		for (i, target) in target.iter_mut().enumerate() {
+			let (x, y) = orientation.map(
+				(i % bounds.w as usize) as i32,
+				(i / bounds.w as usize) as i32,
+				bounds.w,
+				bounds.h,
+			);
+			*target = (y * new_bounds.w + x) as usize;
+		}
+
+		let mut mask = bitvec![Lsb0, u8; 0; len];
+		for (i, bit) in old_mask.iter().enumerate() {
+			if *bit {
+				mask.set(target[i], true);
+			}
+		}
+
+		// Data offset of every destination pixel
+		let mut offsets = vec![0usize; len];
+		let mut count = 0;
+		for (i, bit) in mask.iter().enumerate() {
+			offsets[i] = count;
+			if *bit {
+				count += 1;
+			}
+		}
+
+		let mut data = vec![0u8; old_data.len()];
+		let mut source = old_data.chunks(stride);
+		for (i, bit) in old_mask.iter().enumerate() {
+			if *bit {
+				let offset = offsets[target[i]] * stride;
+				data[offset..offset + stride].copy_from_slice(source.next().unwrap());
+			}
+		}
+
+		unsafe { Stencil::from_raw_parts(new_bounds, mask, channel, data) }
+	}
+}
+
+impl Orientable for Canvas {
+	type Output = Canvas;
+
+	fn orient(&self, orientation: Orientation) -> Self::Output {
+		let bounds = self.bounds();
+		let stencils = self
+			.tiles()
+			.values()
+			.map(|stencil| {
+				let oriented = stencil.orient(orientation);
+				let target = orientation.map_rect(stencil.bounds(), bounds);
+				let mask = oriented.mask().clone();
+				let data = oriented.data().clone();
+				Arc::new(unsafe { Stencil::from_raw_parts(target, mask, self.channel(), data) })
+			})
+			.collect();
+		unsafe { Canvas::from_raw_parts(self.channel(), stencils) }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use color::Channel;
+
+	fn pixels(stencil: &Stencil) -> Vec<u8> {
+		stencil
+			.iter()
+			.flat_map(|(_, _, data)| data.to_vec())
+			.collect()
+	}
+
+	#[test]
+	fn stencil_orient() {
+		// 1 2 3
+		// 4 5 6
+		let a = Stencil::from_buffer(Rect::new(1, 2, 3, 2), Channel::Luma, vec![1, 2, 3, 4, 5, 6]);
+
+		let b = a.flip_horizontal();
+		assert_eq!(b.bounds(), Rect::new(1, 2, 3, 2));
+		assert_eq!(pixels(&b), vec![3, 2, 1, 6, 5, 4]);
+
+		let b = a.flip_vertical();
+		assert_eq!(b.bounds(), Rect::new(1, 2, 3, 2));
+		assert_eq!(pixels(&b), vec![4, 5, 6, 1, 2, 3]);
+
+		let b = a.rotate90();
+		assert_eq!(b.bounds(), Rect::new(1, 2, 2, 3));
+		assert_eq!(pixels(&b), vec![4, 1, 5, 2, 6, 3]);
+
+		let b = a.rotate180();
+		assert_eq!(b.bounds(), Rect::new(1, 2, 3, 2));
+		assert_eq!(pixels(&b), vec![6, 5, 4, 3, 2, 1]);
+
+		let b = a.rotate270();
+		assert_eq!(b.bounds(), Rect::new(1, 2, 2, 3));
+		assert_eq!(pixels(&b), vec![3, 6, 2, 5, 1, 4]);
+
+		let b = a.transpose();
+		assert_eq!(b.bounds(), Rect::new(1, 2, 2, 3));
+		assert_eq!(pixels(&b), vec![1, 4, 2, 5, 3, 6]);
+
+		assert_eq!(pixels(&a.rotate90().rotate270()), pixels(&a));
+		assert_eq!(pixels(&a.rotate90().rotate90()), pixels(&a.rotate180()));
+	}
+
+	#[test]
+	fn stencil_orient_masked() {
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![1, 255, 2, 255, 0, 0, 4, 255],
+		);
+		assert_eq!(format!("{:?}", a), "Stencil ( ⠙ )");
+
+		let b = a.flip_horizontal();
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠋ )");
+		assert_eq!(pixels(&b), vec![2, 255, 1, 255, 4, 255]);
+
+		let b = a.rotate90();
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠚ )");
+		assert_eq!(pixels(&b), vec![1, 255, 4, 255, 2, 255]);
+
+		let b = a.transpose();
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠓ )");
+		assert_eq!(pixels(&b), vec![1, 255, 2, 255, 4, 255]);
+	}
+
+	#[test]
+	fn canvas_orient() {
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 2, 1),
+			Channel::Luma,
+			vec![1, 2],
+		))
+		.apply_stencil(Stencil::from_buffer(
+			Rect::new(2, 1, 1, 1),
+			Channel::Luma,
+			vec![3],
+		))
+		.unwrap();
+		assert_eq!(a.bounds(), Rect::new(0, 0, 3, 2));
+
+		let b = a.flip_horizontal();
+		assert_eq!(b.bounds(), Rect::new(0, 0, 3, 2));
+		let pixels: Vec<_> = b.iter().flatten().copied().collect();
+		assert_eq!(pixels, vec![0, 2, 1, 3, 0, 0]);
+
+		let b = a.rotate90();
+		assert_eq!(b.bounds(), Rect::new(0, 0, 2, 3));
+		let pixels: Vec<_> = b.iter().flatten().copied().collect();
+		assert_eq!(pixels, vec![0, 1, 0, 2, 3, 0]);
+	}
+}
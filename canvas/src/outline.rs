@@ -0,0 +1,194 @@
+use crate::Stencil;
+use std::collections::VecDeque;
+use vek::{geom::repr_c::Rect, vec::repr_c::vec2::Vec2};
+
+/// Side of the mask edge an outline is drawn on
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OutlinePlacement {
+	/// Over the edge pixels of the mask
+	Inside,
+	/// Around the mask, leaving it untouched
+	Outside,
+}
+
+/// Neighbourhood used to grow an outline
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Connectivity {
+	/// Orthogonal neighbours only, giving rounded corners
+	Four,
+	/// Orthogonal and diagonal neighbours, giving square corners
+	Eight,
+}
+
+impl Connectivity {
+	fn neighbours(self) -> &'static [(i32, i32)] {
+		match self {
+			Connectivity::Four => &[(0, -1), (-1, 0), (1, 0), (0, 1)],
+			Connectivity::Eight => &[
+				(-1, -1),
+				(0, -1),
+				(1, -1),
+				(-1, 0),
+				(1, 0),
+				(-1, 1),
+				(0, 1),
+				(1, 1),
+			],
+		}
+	}
+}
+
+pub trait Outlinable {
+	/// Stencil of the pixels within `thickness` steps of the mask edge, filled
+	/// with `color`
+	///
+	/// Only the outline is returned; merge it with the source to draw it.
+	fn outline(
+		&self,
+		placement: OutlinePlacement,
+		connectivity: Connectivity,
+		thickness: u32,
+		color: &[u8],
+	) -> Stencil;
+
+	/// Silhouette of the mask moved by `offset` and filled with `color`
+	fn drop_shadow(&self, offset: Vec2<i32>, color: &[u8]) -> Stencil;
+}
+
+impl Outlinable for Stencil {
+	fn outline(
+		&self,
+		placement: OutlinePlacement,
+		connectivity: Connectivity,
+		thickness: u32,
+		color: &[u8],
+	) -> Stencil {
+		assert_eq!(color.len(), self.channel().pixel_stride());
+		let bounds = self.bounds();
+		let t = thickness as i32;
+		let grid = Rect::new(
+			bounds.x - t,
+			bounds.y - t,
+			bounds.w + 2 * t,
+			bounds.h + 2 * t,
+		);
+		let w = grid.w as usize;
+		let filled = |x: i32, y: i32| self.try_get(x, y).is_some();
+
+		// Breadth-first distance from the other side of the edge
+		let source = placement == OutlinePlacement::Outside;
+		let mut distance = vec![u32::MAX; w * grid.h as usize];
+		let mut queue = VecDeque::new();
+		for y in 0..grid.h {
+			for x in 0..grid.w {
+				if filled(grid.x + x, grid.y + y) == source {
+					distance[y as usize * w + x as usize] = 0;
+					queue.push_back((x, y));
+				}
+			}
+		}
+		while let Some((x, y)) = queue.pop_front() {
+			let next = distance[y as usize * w + x as usize] + 1;
+			if next > thickness {
+				continue;
+			}
+			for (dx, dy) in connectivity.neighbours() {
+				let (nx, ny) = (x + dx, y + dy);
+				if nx < 0 || ny < 0 || nx >= grid.w || ny >= grid.h {
+					continue;
+				}
+				let i = ny as usize * w + nx as usize;
+				if distance[i] == u32::MAX {
+					distance[i] = next;
+					queue.push_back((nx, ny));
+				}
+			}
+		}
+
+		let rect = match placement {
+			OutlinePlacement::Inside => bounds,
+			OutlinePlacement::Outside => grid,
+		};
+		Stencil::from_fn(rect, self.channel(), |x, y, pixel| {
+			let d = distance[(y - grid.y) as usize * w + (x - grid.x) as usize];
+			if d > 0 && d <= thickness {
+				pixel.copy_from_slice(color);
+				true
+			} else {
+				false
+			}
+		})
+	}
+
+	fn drop_shadow(&self, offset: Vec2<i32>, color: &[u8]) -> Stencil {
+		assert_eq!(color.len(), self.channel().pixel_stride());
+		let bounds = self.bounds();
+		let rect = Rect::new(bounds.x + offset.x, bounds.y + offset.y, bounds.w, bounds.h);
+		Stencil::from_fn(rect, self.channel(), |x, y, pixel| {
+			if self.try_get(x - offset.x, y - offset.y).is_some() {
+				pixel.copy_from_slice(color);
+				true
+			} else {
+				false
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use color::{Blending, Channel, Compositing};
+
+	fn dot() -> Stencil {
+		Stencil::from_buffer(Rect::new(0, 0, 1, 1), Channel::Luma, vec![1])
+	}
+
+	#[test]
+	fn outline_outside() {
+		let a = dot().outline(OutlinePlacement::Outside, Connectivity::Four, 1, &[9]);
+		assert_eq!(a.bounds(), Rect::new(-1, -1, 3, 3));
+		assert_eq!(format!("{:?}", a), "Stencil ( ⠪⠂ )");
+		assert_eq!(*a.data(), vec![9, 9, 9, 9]);
+
+		let a = dot().outline(OutlinePlacement::Outside, Connectivity::Eight, 1, &[9]);
+		assert_eq!(format!("{:?}", a), "Stencil ( ⠯⠇ )");
+		assert_eq!(a.data().len(), 8);
+
+		let a = dot().outline(OutlinePlacement::Outside, Connectivity::Four, 2, &[9]);
+		assert_eq!(a.bounds(), Rect::new(-2, -2, 5, 5));
+		assert_eq!(a.data().len(), 12);
+		assert_eq!(a.try_get(-2, 0), Some(&[9][..]));
+		assert_eq!(a.try_get(-2, -2), None);
+		assert_eq!(a.try_get(0, 0), None);
+
+		let a = dot().outline(OutlinePlacement::Outside, Connectivity::Eight, 0, &[9]);
+		assert_eq!(a.data().len(), 0);
+	}
+
+	#[test]
+	fn outline_inside() {
+		let a = Stencil::from_buffer(Rect::new(0, 0, 4, 4), Channel::Luma, vec![1; 16]);
+		let b = a.outline(OutlinePlacement::Inside, Connectivity::Four, 1, &[9]);
+		assert_eq!(b.bounds(), a.bounds());
+		assert_eq!(b.data().len(), 12);
+		assert_eq!(b.try_get(1, 1), None);
+
+		let merged = Stencil::merge(&b, &a, Blending::Normal, Compositing::SourceOver);
+		assert_eq!(merged.try_get(0, 0), Some(&[9][..]));
+		assert_eq!(merged.try_get(1, 1), Some(&[1][..]));
+	}
+
+	#[test]
+	fn drop_shadow() {
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 1),
+			Channel::Lumaa,
+			vec![1, 255, 0, 0],
+		);
+		let b = a.drop_shadow(Vec2::new(1, 2), &[0, 128]);
+		assert_eq!(b.bounds(), Rect::new(1, 2, 2, 1));
+		assert_eq!(b.try_get(1, 2), Some(&[0, 128][..]));
+		assert_eq!(b.try_get(2, 2), None);
+	}
+}
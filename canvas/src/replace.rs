@@ -0,0 +1,185 @@
+use crate::{component_layout, Canvas, Stencil};
+use std::collections::HashMap;
+use vek::geom::repr_c::Rect;
+
+/// Table of color replacements
+///
+/// Colors are the 8-bit components of a pixel, alpha included; float
+/// components such as normals are neither compared nor replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorMap {
+	entries: HashMap<Vec<u8>, Vec<u8>>,
+	tolerance: u8,
+}
+
+impl ColorMap {
+	/// Create an empty map matching colors whose components all differ by at
+	/// most `tolerance`
+	pub fn new(tolerance: u8) -> Self {
+		ColorMap {
+			entries: HashMap::new(),
+			tolerance,
+		}
+	}
+
+	/// Retrieve tolerance
+	pub fn tolerance(&self) -> u8 {
+		self.tolerance
+	}
+
+	/// Retrieve replacements, keyed by the color they replace
+	pub fn entries(&self) -> &HashMap<Vec<u8>, Vec<u8>> {
+		&self.entries
+	}
+
+	/// Add a replacement, unless `from` is already replaced
+	pub fn insert(&mut self, from: &[u8], to: &[u8]) {
+		assert_eq!(from.len(), to.len());
+		self.entries
+			.entry(from.to_vec())
+			.or_insert_with(|| to.to_vec());
+	}
+
+	/// Find the replacement of the closest matching color
+	///
+	/// Exact matches are hashed, colors are only compared one by one with a
+	/// non-zero tolerance. Equally close colors resolve to the smallest one.
+	pub fn lookup(&self, color: &[u8]) -> Option<&[u8]> {
+		if let Some(to) = self.entries.get(color) {
+			return Some(to);
+		}
+		if self.tolerance == 0 {
+			return None;
+		}
+		self.entries
+			.iter()
+			.filter(|(from, _)| from.len() == color.len())
+			.map(|(from, to)| {
+				let distance = from
+					.iter()
+					.zip(color.iter())
+					.map(|(a, b)| (*a as i32 - *b as i32).abs())
+					.max()
+					.unwrap_or(0);
+				(distance, from, to)
+			})
+			.filter(|(distance, _, _)| *distance <= self.tolerance as i32)
+			.min_by_key(|(distance, from, _)| (*distance, *from))
+			.map(|(_, _, to)| to.as_slice())
+	}
+}
+
+impl Default for ColorMap {
+	fn default() -> Self {
+		ColorMap::new(0)
+	}
+}
+
+pub trait Replaceable {
+	type Output;
+
+	/// Replace colors found in map, only within region if any
+	fn replace_colors(&self, map: &ColorMap, region: Option<Rect<i32, i32>>) -> Self::Output;
+}
+
+impl Replaceable for Stencil {
+	type Output = Stencil;
+
+	fn replace_colors(&self, map: &ColorMap, region: Option<Rect<i32, i32>>) -> Self::Output {
+		let (bytes, _) = component_layout(self.channel());
+		let mut stencil = self.clone();
+		for (x, y, pixel) in stencil.iter_mut() {
+			let inside = match region {
+				Some(region) => {
+					x >= region.x
+						&& x < region.x + region.w
+						&& y >= region.y && y < region.y + region.h
+				}
+				None => true,
+			};
+			if inside {
+				if let Some(to) = map.lookup(&pixel[..bytes]) {
+					pixel[..bytes].copy_from_slice(to);
+				}
+			}
+		}
+		stencil
+	}
+}
+
+impl Replaceable for Canvas {
+	type Output = Canvas;
+
+	fn replace_colors(&self, map: &ColorMap, region: Option<Rect<i32, i32>>) -> Self::Output {
+		self.map_tiles(region, |tile| tile.replace_colors(map, region))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use color::Channel;
+	use std::sync::Arc;
+
+	#[test]
+	fn lookup() {
+		let mut map = ColorMap::new(10);
+		map.insert(&[100, 0, 0, 255], &[0, 0, 100, 255]);
+		map.insert(&[110, 0, 0, 255], &[0, 100, 0, 255]);
+		assert_eq!(map.lookup(&[100, 0, 0, 255]), Some(&[0, 0, 100, 255][..]));
+		assert_eq!(map.lookup(&[108, 5, 0, 255]), Some(&[0, 100, 0, 255][..]));
+		assert_eq!(map.lookup(&[95, 0, 0, 250]), Some(&[0, 0, 100, 255][..]));
+		assert_eq!(map.lookup(&[80, 0, 0, 255]), None);
+		assert_eq!(map.lookup(&[100, 0, 0]), None);
+		assert_eq!(ColorMap::default().lookup(&[0]), None);
+
+		// Equally close, the smallest color wins whatever the insertion order
+		let mut map = ColorMap::new(5);
+		map.insert(&[20], &[2]);
+		map.insert(&[10], &[1]);
+		map.insert(&[10], &[3]);
+		assert_eq!(map.lookup(&[15]), Some(&[1][..]));
+		assert_eq!(map.lookup(&[10]), Some(&[1][..]));
+		assert_eq!(ColorMap::default().lookup(&[1]), None);
+	}
+
+	#[test]
+	fn stencil_replace() {
+		let mut map = ColorMap::default();
+		map.insert(&[1, 255], &[9, 255]);
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 3, 1),
+			Channel::Lumaa,
+			vec![1, 255, 1, 0, 1, 255],
+		);
+		let b = a.replace_colors(&map, None);
+		assert_eq!(*b.data(), vec![9, 255, 9, 255]);
+		let b = a.replace_colors(&map, Some(Rect::new(1, 0, 5, 5)));
+		assert_eq!(*b.data(), vec![1, 255, 9, 255]);
+	}
+
+	#[test]
+	fn canvas_replace() {
+		let mut map = ColorMap::new(2);
+		map.insert(&[10, 20, 30], &[1, 2, 3]);
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 66, 1),
+			Channel::Rgb,
+			(0..66).flat_map(|_| vec![11, 20, 29]).collect(),
+		))
+		.apply_stencil(Stencil::from_buffer(
+			Rect::new(0, 100, 1, 1),
+			Channel::Rgb,
+			vec![10, 20, 30],
+		))
+		.unwrap();
+		let b = a.replace_colors(&map, Some(Rect::new(60, 0, 10, 10)));
+		assert_eq!(b[(59, 0)], [11, 20, 29]);
+		assert_eq!(b[(64, 0)], [1, 2, 3]);
+		assert_eq!(b[(0, 100)], [10, 20, 30]);
+		assert!(Arc::ptr_eq(&a.tiles()[&(0, 1)], &b.tiles()[&(0, 1)]));
+		let b = a.replace_colors(&map, None);
+		assert_eq!(b[(0, 0)], [1, 2, 3]);
+		assert_eq!(b[(0, 100)], [1, 2, 3]);
+	}
+}
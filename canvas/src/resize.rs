@@ -0,0 +1,180 @@
+use crate::{Addressing, Canvas, Sampling, Transformable};
+use color::ChannelError;
+use vek::{
+	geom::repr_c::Rect,
+	mat::repr_c::column_major::Mat3,
+	vec::repr_c::{vec2::Vec2, vec3::Vec3},
+};
+
+/// Point of the bounds that stays in place when the working area changes
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Anchor {
+	#[default]
+	TopLeft,
+	Top,
+	TopRight,
+	Left,
+	Center,
+	Right,
+	BottomLeft,
+	Bottom,
+	BottomRight,
+}
+
+impl Anchor {
+	/// Area of `width` by `height` placed within or around bounds
+	///
+	/// When the size difference is odd, centered areas lean to the top-left.
+	pub fn place(self, bounds: Rect<i32, i32>, width: i32, height: i32) -> Rect<i32, i32> {
+		let (fx, fy) = match self {
+			Anchor::TopLeft => (0, 0),
+			Anchor::Top => (1, 0),
+			Anchor::TopRight => (2, 0),
+			Anchor::Left => (0, 1),
+			Anchor::Center => (1, 1),
+			Anchor::Right => (2, 1),
+			Anchor::BottomLeft => (0, 2),
+			Anchor::Bottom => (1, 2),
+			Anchor::BottomRight => (2, 2),
+		};
+		Rect::new(
+			bounds.x + ((bounds.w - width) * fx).div_euclid(2),
+			bounds.y + ((bounds.h - height) * fy).div_euclid(2),
+			width,
+			height,
+		)
+	}
+}
+
+impl Canvas {
+	/// Scale the image to exactly `width` by `height` pixels, keeping its
+	/// top-left corner in place
+	pub fn resize(
+		&self,
+		width: i32,
+		height: i32,
+		sampling: Sampling,
+	) -> Result<Canvas, ChannelError> {
+		let bounds = self.bounds();
+		if bounds.w <= 0 || bounds.h <= 0 || width <= 0 || height <= 0 {
+			return Ok(Canvas::new(self.channel()));
+		}
+		let scale = Mat3::scaling_3d(Vec3::new(
+			width as f32 / bounds.w as f32,
+			height as f32 / bounds.h as f32,
+			1.,
+		));
+		let pivot = Vec2::new(bounds.x as f32, bounds.y as f32);
+		self.transform_around(sampling, Addressing::Clamp, pivot, &scale)
+	}
+
+	/// Change the working area to `width` by `height` pixels without scaling
+	///
+	/// Pixels outside the new area are dropped. The area is kept as the
+	/// canvas' working area, so it stays its bounds without painting the
+	/// uncovered pixels.
+	pub fn resize_canvas(&self, width: i32, height: i32, anchor: Anchor) -> Canvas {
+		let area = anchor.place(self.bounds(), width.max(0), height.max(0));
+		self.clip(area).with_area(Some(area))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::Stencil;
+	use color::Channel;
+
+	#[test]
+	fn anchor_place() {
+		let bounds = Rect::new(10, 10, 4, 4);
+		assert_eq!(Anchor::TopLeft.place(bounds, 2, 2), Rect::new(10, 10, 2, 2));
+		assert_eq!(Anchor::Center.place(bounds, 2, 2), Rect::new(11, 11, 2, 2));
+		assert_eq!(
+			Anchor::BottomRight.place(bounds, 2, 2),
+			Rect::new(12, 12, 2, 2)
+		);
+		assert_eq!(Anchor::Center.place(bounds, 7, 8), Rect::new(8, 8, 7, 8));
+		assert_eq!(Anchor::Right.place(bounds, 6, 4), Rect::new(8, 10, 6, 4));
+	}
+
+	#[test]
+	fn resize() {
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(5, 5, 2, 2),
+			Channel::Luma,
+			vec![1, 2, 3, 4],
+		));
+		let b = a.resize(4, 6, Sampling::Nearest).unwrap();
+		assert_eq!(b.bounds(), Rect::new(5, 5, 4, 6));
+		let pixels: Vec<_> = b.iter().flatten().copied().collect();
+		assert_eq!(
+			pixels,
+			vec![1, 1, 2, 2, 1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4, 3, 3, 4, 4]
+		);
+
+		let b = a.resize(1, 1, Sampling::Bilinear).unwrap();
+		assert_eq!(b.bounds(), Rect::new(5, 5, 1, 1));
+		assert_eq!(b[(5, 5)], [3]);
+
+		assert_eq!(a.resize(0, 3, Sampling::Nearest).unwrap().bounds().w, 0);
+	}
+
+	#[test]
+	fn resize_canvas() {
+		let a = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![1, 255, 2, 255, 3, 255, 4, 255],
+		));
+		let b = a.resize_canvas(4, 3, Anchor::Center);
+		assert_eq!(b.bounds(), Rect::new(-1, -1, 4, 3));
+		assert_eq!(b[(0, 0)], [1, 255]);
+		assert_eq!(b[(1, 1)], [4, 255]);
+		assert_eq!(b[(-1, 0)], [0, 0]);
+		assert_eq!(b[(0, -1)], [0, 0]);
+
+		assert_eq!(b.try_get(-1, 0), None);
+		assert_eq!(b.area(), Some(Rect::new(-1, -1, 4, 3)));
+		assert!(b.check().is_ok());
+
+		// The working area survives compacting and trimming
+		let (c, _) = b.compact();
+		assert_eq!(c.bounds(), Rect::new(-1, -1, 4, 3));
+		assert!(c.check().is_ok());
+		assert_eq!(b.trim().bounds(), Rect::new(-1, -1, 4, 3));
+
+		let b = a.resize_canvas(1, 1, Anchor::BottomRight);
+		assert_eq!(b.bounds(), Rect::new(1, 1, 1, 1));
+		assert_eq!(b[(1, 1)], [4, 255]);
+
+		let b = a.resize_canvas(0, 3, Anchor::TopLeft);
+		assert!(b.tiles().is_empty());
+		assert_eq!(b.bounds(), Rect::new(0, 0, 0, 3));
+		assert!(b.check().is_ok());
+	}
+
+	#[test]
+	fn resize_canvas_sparse() {
+		// Padding leaves channels without alpha untouched and tiles unallocated
+		let a = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 1, 1),
+			Channel::Rgb,
+			vec![1, 2, 3],
+		));
+		let b = a.resize_canvas(1000, 1000, Anchor::TopLeft);
+		assert_eq!(b.bounds(), Rect::new(0, 0, 1000, 1000));
+		assert_eq!(b.try_get(0, 0), Some(&[1, 2, 3][..]));
+		assert_eq!(b.try_get(1, 0), None);
+		assert_eq!(b.try_get(999, 999), None);
+		assert_eq!(b.tiles().len(), 1);
+		assert_eq!(
+			b.tiles()
+				.values()
+				.map(|tile| tile.data().len())
+				.sum::<usize>(),
+			3
+		);
+		assert!(b.check().is_ok());
+	}
+}
@@ -1,12 +1,13 @@
 use crate::{Canvas, Stencil};
-use color::{ChannelError, Pixel, PixelMut};
-use vek::vec::repr_c::vec2::Vec2;
+use color::{Alpha, Channel, ChannelError, Pixel, PixelMut, Rgb, Rgba};
+use vek::{geom::repr_c::Rect, vec::repr_c::vec2::Vec2};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Sampling {
 	Nearest,
 	Bilinear,
-	// Bicubic,
+	Bicubic,
+	Lanczos3,
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -34,6 +35,38 @@ impl From<ChannelError> for SamplingError {
 	}
 }
 
+/// Based on [Vulkan specs](https://www.khronos.org/registry/vulkan/specs/1.2-extensions/html/vkspec.html#textures-wrapping-operation)
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Addressing {
+	/// Coordinates are clamped to the edge pixels
+	Clamp,
+	/// Coordinates outside bounds are transparent and report `SamplingError::OutOfBounds`
+	#[default]
+	Border,
+	/// Coordinates wrap around
+	Repeat,
+	/// Coordinates wrap around, flipping every other repetition
+	MirroredRepeat,
+}
+
+impl Addressing {
+	/// Resolve a coordinate inside `[start, start + len)`
+	pub fn resolve(&self, coord: i32, start: i32, len: i32) -> i32 {
+		match self {
+			Addressing::Clamp | Addressing::Border => coord.clamp(start, start + len - 1),
+			Addressing::Repeat => start + (coord - start).rem_euclid(len),
+			Addressing::MirroredRepeat => {
+				let offset = (coord - start).rem_euclid(len * 2);
+				if offset < len {
+					start + offset
+				} else {
+					start + len * 2 - 1 - offset
+				}
+			}
+		}
+	}
+}
+
 pub trait Samplable {
 	type Error: std::error::Error;
 
@@ -41,6 +74,7 @@ pub trait Samplable {
 		&'samplable self,
 		position: (f32, f32),
 		sampling: Sampling,
+		addressing: Addressing,
 		out: &'out mut [u8],
 	) -> Result<(), Self::Error>;
 }
@@ -52,52 +86,118 @@ impl Samplable for Canvas {
 		&'samplable self,
 		position: (f32, f32),
 		sampling: Sampling,
+		addressing: Addressing,
 		out: &'out mut [u8],
 	) -> Result<(), Self::Error> {
 		let channel = self.channel();
 		assert_eq!(out.len(), channel.pixel_stride());
-		let bounds = self.bounds();
-		if !bounds.contains_point(Vec2::new(position.0 as i32, position.1 as i32)) {
+		let result = sample(
+			|x, y| Some(&self[(x, y)]),
+			self.bounds(),
+			position,
+			sampling,
+			addressing,
+			channel,
+			out,
+		);
+		if let Err(SamplingError::OutOfBounds) = result {
 			out.copy_from_slice(&channel.default_pixel());
-			return Err(SamplingError::OutOfBounds);
 		}
-		match sampling {
-			Sampling::Nearest => {
-				let x = position
-					.0
-					.round()
-					.clamp(bounds.x as f32, bounds.w as f32 - 1.);
-				let y = position
-					.1
-					.round()
-					.clamp(bounds.y as f32, bounds.h as f32 - 1.);
-				out.copy_from_slice(&self[(x as i32, y as i32)]);
+		result
+	}
+}
+
+impl Samplable for Stencil {
+	type Error = SamplingError;
+
+	fn sample2d<'samplable, 'out>(
+		&'samplable self,
+		position: (f32, f32),
+		sampling: Sampling,
+		addressing: Addressing,
+		out: &'out mut [u8],
+	) -> Result<(), Self::Error> {
+		let channel = self.channel();
+		assert_eq!(out.len(), channel.pixel_stride());
+		sample(
+			|x, y| self.try_get(x, y),
+			self.bounds(),
+			position,
+			sampling,
+			addressing,
+			channel,
+			out,
+		)
+	}
+}
+
+/// Sample pixels around position
+///
+/// Taps are resolved inside bounds with the addressing mode. Taps returning `None`
+/// are considered empty and sampled as the Channel's default pixel. If every tap
+/// carrying weight is empty, `SamplingError::Empty` is returned.
+fn sample<'a, F: Fn(i32, i32) -> Option<&'a [u8]>>(
+	fetch: F,
+	bounds: Rect<i32, i32>,
+	position: (f32, f32),
+	sampling: Sampling,
+	addressing: Addressing,
+	channel: Channel,
+	out: &mut [u8],
+) -> Result<(), SamplingError> {
+	if bounds.w <= 0 || bounds.h <= 0 {
+		return Err(SamplingError::OutOfBounds);
+	}
+	if addressing == Addressing::Border
+		&& !bounds.contains_point(Vec2::new(position.0 as i32, position.1 as i32))
+	{
+		return Err(SamplingError::OutOfBounds);
+	}
+	let resolve_x = |x: f32| addressing.resolve(x as i32, bounds.x, bounds.w);
+	let resolve_y = |y: f32| addressing.resolve(y as i32, bounds.y, bounds.h);
+	match sampling {
+		Sampling::Nearest => {
+			let x = resolve_x(position.0.round());
+			let y = resolve_y(position.1.round());
+			if let Some(data) = fetch(x, y) {
+				out.copy_from_slice(data);
 				Ok(())
+			} else {
+				Err(SamplingError::Empty)
 			}
-			Sampling::Bilinear => {
-				let l = position
-					.0
-					.floor()
-					.clamp(bounds.x as f32, bounds.w as f32 - 1.);
-				let r = (l + 1f32).clamp(bounds.x as f32, bounds.w as f32 - 1.);
-				let t = position
-					.1
-					.floor()
-					.clamp(bounds.y as f32, bounds.h as f32 - 1.);
-				let b = (t + 1f32).clamp(bounds.y as f32, bounds.h as f32 - 1.);
-				let hw = position.0 - l;
-				let vw = position.1 - t;
-
-				let (tl, tr, bl, br) = (
-					&self[(l as i32, t as i32)],
-					&self[(r as i32, t as i32)],
-					&self[(l as i32, b as i32)],
-					&self[(r as i32, b as i32)],
-				);
+		}
+		Sampling::Bilinear => {
+			// Clamping modes anchor the first tap inside bounds before weighting
+			let (l, t) = match addressing {
+				Addressing::Clamp | Addressing::Border => (
+					position
+						.0
+						.floor()
+						.clamp(bounds.x as f32, (bounds.x + bounds.w - 1) as f32),
+					position
+						.1
+						.floor()
+						.clamp(bounds.y as f32, (bounds.y + bounds.h - 1) as f32),
+				),
+				_ => (position.0.floor(), position.1.floor()),
+			};
+			let hw = position.0 - l;
+			let vw = position.1 - t;
+			let (l, r) = (resolve_x(l), resolve_x(l + 1.));
+			let (t, b) = (resolve_y(t), resolve_y(t + 1.));
+
+			let (tl, tr, bl, br) = (fetch(l, t), fetch(r, t), fetch(l, b), fetch(r, b));
 
+			if let (None, None, None, None) = (tl, tr, bl, br) {
+				Err(SamplingError::Empty)
+			} else {
+				let empty = channel.default_pixel();
+				let tl = tl.unwrap_or(&empty);
+				let tr = tr.unwrap_or(&empty);
+				let bl = bl.unwrap_or(&empty);
+				let br = br.unwrap_or(&empty);
 				let mut w_buf = channel.default_pixel();
 				let mut v_buf = channel.default_pixel();
-				let mut r_buf = channel.default_pixel();
 				{
 					let mut tmp = PixelMut::from_buffer_mut(&mut w_buf, channel);
 					let from = Pixel::from_buffer(tl, channel);
@@ -110,109 +210,194 @@ impl Samplable for Canvas {
 					let to = Pixel::from_buffer(br, channel);
 					tmp.lerp(&from, &to, hw)?;
 				}
-
 				{
-					let mut tmp = PixelMut::from_buffer_mut(&mut r_buf, channel);
+					let mut tmp = PixelMut::from_buffer_mut(out, channel);
 					let from = Pixel::from_buffer(&w_buf, channel);
 					let to = Pixel::from_buffer(&v_buf, channel);
 					tmp.lerp(&from, &to, vw)?;
 				}
-				out.copy_from_slice(&r_buf);
 				Ok(())
 			}
 		}
+		Sampling::Bicubic => sample_kernel(
+			fetch,
+			|x, y| (resolve_x(x), resolve_y(y)),
+			position,
+			2,
+			catmull_rom,
+			channel,
+			out,
+		),
+		Sampling::Lanczos3 => sample_kernel(
+			fetch,
+			|x, y| (resolve_x(x), resolve_y(y)),
+			position,
+			3,
+			lanczos3,
+			channel,
+			out,
+		),
 	}
 }
 
-impl Samplable for Stencil {
-	type Error = SamplingError;
+/// Catmull-Rom cubic convolution kernel (a = -0.5)
+fn catmull_rom(d: f32) -> f32 {
+	let d = d.abs();
+	if d < 1. {
+		1.5 * d * d * d - 2.5 * d * d + 1.
+	} else if d < 2. {
+		-0.5 * d * d * d + 2.5 * d * d - 4. * d + 2.
+	} else {
+		0.
+	}
+}
 
-	fn sample2d<'samplable, 'out>(
-		&'samplable self,
-		position: (f32, f32),
-		sampling: Sampling,
-		out: &'out mut [u8],
-	) -> Result<(), Self::Error> {
-		let channel = self.channel();
-		assert_eq!(out.len(), channel.pixel_stride());
-		let bounds = self.bounds();
-		if !bounds.contains_point(Vec2::new(position.0 as i32, position.1 as i32)) {
-			return Err(SamplingError::OutOfBounds);
+/// Lanczos kernel with a window of 3 lobes
+fn lanczos3(d: f32) -> f32 {
+	if d == 0. {
+		1.
+	} else if d.abs() < 3. {
+		let pd = std::f32::consts::PI * d;
+		3. * pd.sin() * (pd / 3.).sin() / (pd * pd)
+	} else {
+		0.
+	}
+}
+
+/// Number of 8-bit and 32-bit float components stored in a pixel of this Channel
+pub(crate) fn component_layout(channel: Channel) -> (usize, usize) {
+	match channel {
+		Channel::Luma => (1, 0),
+		Channel::Lumaa => (2, 0),
+		Channel::Rgb => (3, 0),
+		Channel::Rgba => (4, 0),
+		Channel::Uv => (0, 2),
+		Channel::Normal => (0, 3),
+		Channel::LumaNormal => (1, 3),
+		Channel::LumaaNormal => (2, 3),
+		Channel::RgbNormal => (3, 3),
+		Channel::RgbaNormal => (4, 3),
+	}
+}
+
+/// Index of the alpha component of a channel, if any
+pub(crate) fn alpha_component(channel: Channel) -> Option<usize> {
+	match channel {
+		Channel::Lumaa | Channel::LumaaNormal => Some(1),
+		Channel::Rgba | Channel::RgbaNormal => Some(3),
+		_ => None,
+	}
+}
+
+/// Decode every component of a pixel as floats, 8-bit components stay in 0..255
+pub(crate) fn components(channel: Channel, pixel: &[u8]) -> [f32; 7] {
+	let (bytes, floats) = component_layout(channel);
+	let mut values = [0f32; 7];
+	for (i, value) in values.iter_mut().enumerate().take(bytes) {
+		*value = pixel[i] as f32;
+	}
+	for i in 0..floats {
+		let offset = bytes + i * 4;
+		let mut raw = [0u8; 4];
+		raw.copy_from_slice(&pixel[offset..offset + 4]);
+		values[bytes + i] = f32::from_ne_bytes(raw);
+	}
+	values
+}
+
+/// Encode components into a pixel, 8-bit components are rounded and clamped
+pub(crate) fn write_components(channel: Channel, values: &[f32; 7], out: &mut [u8]) {
+	let (bytes, floats) = component_layout(channel);
+	for (i, value) in values.iter().enumerate().take(bytes) {
+		out[i] = value.round().clamp(0., 255.) as u8;
+	}
+	for i in 0..floats {
+		let offset = bytes + i * 4;
+		out[offset..offset + 4].copy_from_slice(&values[bytes + i].to_ne_bytes());
+	}
+}
+
+/// Read the color components of a pixel
+pub(crate) fn read_color(channel: Channel, data: &[u8]) -> Rgba {
+	let pixel = Pixel::from_buffer(data, channel);
+	match channel {
+		Channel::Luma | Channel::LumaNormal => Rgb::from(*pixel.luma().unwrap()).into(),
+		Channel::Lumaa | Channel::LumaaNormal => (*pixel.lumaa().unwrap()).into(),
+		Channel::Rgb | Channel::RgbNormal => (*pixel.rgb().unwrap()).into(),
+		Channel::Rgba | Channel::RgbaNormal => *pixel.rgba().unwrap(),
+		Channel::Uv | Channel::Normal => Alpha::new(Rgb::new(0, 0, 0), 0),
+	}
+}
+
+/// Accumulate weighted pixels component-wise
+///
+/// 8-bit components are rounded and clamped, float components are written as is.
+pub(crate) fn accumulate<'a, I: IntoIterator<Item = (&'a [u8], f32)>>(
+	channel: Channel,
+	pixels: I,
+	out: &mut [u8],
+) {
+	let mut sums = [0f32; 7];
+	for (pixel, weight) in pixels {
+		for (sum, value) in sums.iter_mut().zip(components(channel, pixel).iter()) {
+			*sum += value * weight;
 		}
-		match sampling {
-			Sampling::Nearest => {
-				let x = position
-					.0
-					.round()
-					.clamp(bounds.x as f32, bounds.w as f32 - 1.);
-				let y = position
-					.1
-					.round()
-					.clamp(bounds.y as f32, bounds.h as f32 - 1.);
-				if let Some(data) = self.try_get(x as i32, y as i32) {
-					out.copy_from_slice(data);
-					Ok(())
-				} else {
-					Err(SamplingError::Empty)
-				}
-			}
-			Sampling::Bilinear => {
-				let l = position
-					.0
-					.floor()
-					.clamp(bounds.x as f32, bounds.w as f32 - 1.);
-				let r = (l + 1f32).clamp(bounds.x as f32, bounds.w as f32 - 1.);
-				let t = position
-					.1
-					.floor()
-					.clamp(bounds.y as f32, bounds.h as f32 - 1.);
-				let b = (t + 1f32).clamp(bounds.y as f32, bounds.h as f32 - 1.);
-				let hw = position.0 - l;
-				let vw = position.1 - t;
-
-				let (tl, tr, bl, br) = (
-					&self.try_get(l as i32, t as i32),
-					&self.try_get(r as i32, t as i32),
-					&self.try_get(l as i32, b as i32),
-					&self.try_get(r as i32, b as i32),
-				);
-
-				if let (None, None, None, None) = (tl, tr, bl, br) {
-					Err(SamplingError::Empty)
-				} else {
-					let empty = channel.default_pixel();
-					let tl = tl.or_else(|| Some(&empty)).take().unwrap();
-					let tr = tr.or_else(|| Some(&empty)).take().unwrap();
-					let bl = bl.or_else(|| Some(&empty)).take().unwrap();
-					let br = br.or_else(|| Some(&empty)).take().unwrap();
-					let mut w_buf = channel.default_pixel();
-					let mut v_buf = channel.default_pixel();
-					let mut r_buf = channel.default_pixel();
-					{
-						let mut tmp = PixelMut::from_buffer_mut(&mut w_buf, channel);
-						let from = Pixel::from_buffer(tl, channel);
-						let to = Pixel::from_buffer(tr, channel);
-						tmp.lerp(&from, &to, hw)?;
-					}
-					{
-						let mut tmp = PixelMut::from_buffer_mut(&mut v_buf, channel);
-						let from = Pixel::from_buffer(bl, channel);
-						let to = Pixel::from_buffer(br, channel);
-						tmp.lerp(&from, &to, hw)?;
-					}
-
-					{
-						let mut tmp = PixelMut::from_buffer_mut(&mut r_buf, channel);
-						let from = Pixel::from_buffer(&w_buf, channel);
-						let to = Pixel::from_buffer(&v_buf, channel);
-						tmp.lerp(&from, &to, vw)?;
-					}
-					out.copy_from_slice(&r_buf);
-					Ok(())
+	}
+	write_components(channel, &sums, out);
+}
+
+/// Sample a separable convolution kernel of a given radius around position
+fn sample_kernel<'a, F, R>(
+	fetch: F,
+	resolve: R,
+	position: (f32, f32),
+	radius: i32,
+	kernel: fn(f32) -> f32,
+	channel: Channel,
+	out: &mut [u8],
+) -> Result<(), SamplingError>
+where
+	F: Fn(i32, i32) -> Option<&'a [u8]>,
+	R: Fn(f32, f32) -> (i32, i32),
+{
+	let empty = channel.default_pixel();
+	let fx = position.0.floor();
+	let fy = position.1.floor();
+	let tx = position.0 - fx;
+	let ty = position.1 - fy;
+	let taps = (2 * radius) as usize;
+	let mut wx = Vec::with_capacity(taps);
+	let mut wy = Vec::with_capacity(taps);
+	for i in (1 - radius)..=radius {
+		wx.push(kernel(i as f32 - tx));
+		wy.push(kernel(i as f32 - ty));
+	}
+	let sx: f32 = wx.iter().sum();
+	let sy: f32 = wy.iter().sum();
+
+	let mut pixels = Vec::with_capacity(taps * taps);
+	let mut found = false;
+	for (j, wy) in wy.iter().enumerate() {
+		for (i, wx) in wx.iter().enumerate() {
+			let (x, y) = resolve(
+				fx + (i as i32 + 1 - radius) as f32,
+				fy + (j as i32 + 1 - radius) as f32,
+			);
+			let weight = (wx / sx) * (wy / sy);
+			match fetch(x, y) {
+				Some(pixel) => {
+					found |= weight.abs() > f32::EPSILON;
+					pixels.push((pixel, weight));
 				}
+				None => pixels.push((&empty[..], weight)),
 			}
 		}
 	}
+	if !found {
+		return Err(SamplingError::Empty);
+	}
+	accumulate(channel, pixels, out);
+	Ok(())
 }
 
 #[cfg(test)]
@@ -232,32 +417,47 @@ mod tests {
 
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0., 0.), Sampling::Nearest, &mut buffer)
+			.sample2d((0., 0.), Sampling::Nearest, Addressing::Border, &mut buffer)
 			.unwrap();
 		assert_eq!(buffer, vec![5, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0.5, 0.), Sampling::Nearest, &mut buffer)
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Nearest,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![10, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((1., 0.), Sampling::Nearest, &mut buffer)
+			.sample2d((1., 0.), Sampling::Nearest, Addressing::Border, &mut buffer)
 			.unwrap();
 		assert_eq!(buffer, vec![10, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0., 0.5), Sampling::Nearest, &mut buffer)
+			.sample2d(
+				(0., 0.5),
+				Sampling::Nearest,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![15, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0.5, 0.5), Sampling::Nearest, &mut buffer)
+			.sample2d(
+				(0.5, 0.5),
+				Sampling::Nearest,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![20, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((1., 1.), Sampling::Nearest, &mut buffer)
+			.sample2d((1., 1.), Sampling::Nearest, Addressing::Border, &mut buffer)
 			.unwrap();
 		assert_eq!(buffer, vec![20, 255]);
 	}
@@ -272,32 +472,62 @@ mod tests {
 
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0., 0.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0., 0.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![5, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0.5, 0.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![8, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((1., 0.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(1., 0.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![10, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0., 0.5), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0., 0.5),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![10, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((0.5, 0.5), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0.5, 0.5),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![13, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		canvas
-			.sample2d((1., 1.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(1., 1.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![20, 255]);
 	}
@@ -312,36 +542,56 @@ mod tests {
 
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((0., 0.), Sampling::Nearest, &mut buffer)
+			.sample2d((0., 0.), Sampling::Nearest, Addressing::Border, &mut buffer)
 			.unwrap();
 		assert_eq!(buffer, vec![5, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((0.5, 0.), Sampling::Nearest, &mut buffer)
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Nearest,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![10, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((1., 0.), Sampling::Nearest, &mut buffer)
+			.sample2d((1., 0.), Sampling::Nearest, Addressing::Border, &mut buffer)
 			.unwrap();
 		assert_eq!(buffer, vec![10, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
-		let res = stencil.sample2d((0., 0.5), Sampling::Nearest, &mut buffer);
+		let res = stencil.sample2d(
+			(0., 0.5),
+			Sampling::Nearest,
+			Addressing::Border,
+			&mut buffer,
+		);
 		assert_eq!(res, Err(SamplingError::Empty));
 		assert_eq!(buffer, vec![0, 0]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((0.5, 0.5), Sampling::Nearest, &mut buffer)
+			.sample2d(
+				(0.5, 0.5),
+				Sampling::Nearest,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![20, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((1., 1.), Sampling::Nearest, &mut buffer)
+			.sample2d((1., 1.), Sampling::Nearest, Addressing::Border, &mut buffer)
 			.unwrap();
 		assert_eq!(buffer, vec![20, 255]);
 
 		let mut buffer = Channel::Lumaa.default_pixel();
-		let res = stencil.sample2d((-1., 0.), Sampling::Nearest, &mut buffer);
+		let res = stencil.sample2d(
+			(-1., 0.),
+			Sampling::Nearest,
+			Addressing::Border,
+			&mut buffer,
+		);
 		assert_eq!(res, Err(SamplingError::OutOfBounds));
 		assert_eq!(buffer, vec![0, 0]);
 	}
@@ -356,33 +606,393 @@ mod tests {
 
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((0., 0.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0., 0.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![5, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((0.5, 0.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![8, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((1., 0.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(1., 0.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![10, 255]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((0., 0.5), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0., 0.5),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![3, 128]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((0.5, 0.5), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(0.5, 0.5),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![9, 192]);
 		let mut buffer = Channel::Lumaa.default_pixel();
 		stencil
-			.sample2d((1., 1.), Sampling::Bilinear, &mut buffer)
+			.sample2d(
+				(1., 1.),
+				Sampling::Bilinear,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![20, 255]);
+	}
+
+	#[test]
+	fn canvas_bicubic_sample() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![5, 255, 10, 255, 15, 255, 20, 255],
+		));
+
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d((0., 0.), Sampling::Bicubic, Addressing::Border, &mut buffer)
+			.unwrap();
+		assert_eq!(buffer, vec![5, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Bicubic,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![8, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(0.5, 0.5),
+				Sampling::Bicubic,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![13, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d((1., 1.), Sampling::Bicubic, Addressing::Border, &mut buffer)
+			.unwrap();
+		assert_eq!(buffer, vec![20, 255]);
+	}
+
+	#[test]
+	fn canvas_lanczos_sample() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![5, 255, 10, 255, 15, 255, 20, 255],
+		));
+
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(0., 0.),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![5, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![7, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(0.5, 0.5),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![13, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(1., 1.),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
 			.unwrap();
 		assert_eq!(buffer, vec![20, 255]);
 	}
+
+	#[test]
+	fn stencil_bicubic_sample() {
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![5, 255, 10, 255, 0, 0, 20, 255],
+		);
+
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d((0., 0.), Sampling::Bicubic, Addressing::Border, &mut buffer)
+			.unwrap();
+		assert_eq!(buffer, vec![5, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Bicubic,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![8, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d(
+				(0., 0.5),
+				Sampling::Bicubic,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![3, 128]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d((1., 1.), Sampling::Bicubic, Addressing::Border, &mut buffer)
+			.unwrap();
+		assert_eq!(buffer, vec![20, 255]);
+
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 1),
+			Channel::Lumaa,
+			vec![0, 0, 20, 255],
+		);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		let res = stencil.sample2d((0., 0.), Sampling::Bicubic, Addressing::Border, &mut buffer);
+		assert_eq!(res, Err(SamplingError::Empty));
+	}
+
+	#[test]
+	fn stencil_lanczos_sample() {
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![5, 255, 10, 255, 0, 0, 20, 255],
+		);
+
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d(
+				(0., 0.),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![5, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d(
+				(0.5, 0.),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![7, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d(
+				(0., 0.5),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![2, 127]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d(
+				(1., 1.),
+				Sampling::Lanczos3,
+				Addressing::Border,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![20, 255]);
+
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 1),
+			Channel::Lumaa,
+			vec![0, 0, 20, 255],
+		);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		let res = stencil.sample2d(
+			(0., 0.),
+			Sampling::Lanczos3,
+			Addressing::Border,
+			&mut buffer,
+		);
+		assert_eq!(res, Err(SamplingError::Empty));
+	}
+
+	#[test]
+	fn addressing_resolve() {
+		assert_eq!(Addressing::Clamp.resolve(-1, 0, 3), 0);
+		assert_eq!(Addressing::Clamp.resolve(5, 0, 3), 2);
+		assert_eq!(Addressing::Border.resolve(5, 0, 3), 2);
+		assert_eq!(Addressing::Repeat.resolve(-1, 0, 3), 2);
+		assert_eq!(Addressing::Repeat.resolve(4, 0, 3), 1);
+		assert_eq!(Addressing::Repeat.resolve(9, 10, 3), 12);
+		assert_eq!(Addressing::MirroredRepeat.resolve(-1, 0, 3), 0);
+		assert_eq!(Addressing::MirroredRepeat.resolve(3, 0, 3), 2);
+		assert_eq!(Addressing::MirroredRepeat.resolve(4, 0, 3), 1);
+		assert_eq!(Addressing::MirroredRepeat.resolve(6, 0, 3), 0);
+	}
+
+	#[test]
+	fn canvas_addressing_sample() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![5, 255, 10, 255, 15, 255, 20, 255],
+		));
+
+		let mut buffer = Channel::Lumaa.default_pixel();
+		let res = canvas.sample2d((3., 0.), Sampling::Nearest, Addressing::Border, &mut buffer);
+		assert_eq!(res, Err(SamplingError::OutOfBounds));
+		assert_eq!(buffer, vec![0, 0]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d((2., 0.), Sampling::Nearest, Addressing::Clamp, &mut buffer)
+			.unwrap();
+		assert_eq!(buffer, vec![10, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d((2., 0.), Sampling::Nearest, Addressing::Repeat, &mut buffer)
+			.unwrap();
+		assert_eq!(buffer, vec![5, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(2., 0.),
+				Sampling::Nearest,
+				Addressing::MirroredRepeat,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![10, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(-1., 3.),
+				Sampling::Nearest,
+				Addressing::Repeat,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![20, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(1.5, 0.),
+				Sampling::Bilinear,
+				Addressing::Repeat,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![8, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(1.5, 0.),
+				Sampling::Bilinear,
+				Addressing::Clamp,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![10, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		canvas
+			.sample2d(
+				(1.5, 0.),
+				Sampling::Bicubic,
+				Addressing::Repeat,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![8, 255]);
+	}
+
+	#[test]
+	fn stencil_addressing_sample() {
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![5, 255, 10, 255, 0, 0, 20, 255],
+		);
+
+		let mut buffer = Channel::Lumaa.default_pixel();
+		let res = stencil.sample2d(
+			(-1., 1.),
+			Sampling::Nearest,
+			Addressing::Border,
+			&mut buffer,
+		);
+		assert_eq!(res, Err(SamplingError::OutOfBounds));
+		let mut buffer = Channel::Lumaa.default_pixel();
+		let res = stencil.sample2d((-1., 1.), Sampling::Nearest, Addressing::Clamp, &mut buffer);
+		assert_eq!(res, Err(SamplingError::Empty));
+		let mut buffer = Channel::Lumaa.default_pixel();
+		stencil
+			.sample2d(
+				(-1., 1.),
+				Sampling::Nearest,
+				Addressing::Repeat,
+				&mut buffer,
+			)
+			.unwrap();
+		assert_eq!(buffer, vec![20, 255]);
+		let mut buffer = Channel::Lumaa.default_pixel();
+		let res = stencil.sample2d(
+			(-1., 1.),
+			Sampling::Nearest,
+			Addressing::MirroredRepeat,
+			&mut buffer,
+		);
+		assert_eq!(res, Err(SamplingError::Empty));
+	}
 }
@@ -1,17 +1,35 @@
 use crate::braille::braille_fmt2;
 use bitvec::{bitvec, order::Lsb0, vec::BitVec};
 use color::*;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use vek::geom::repr_c::Rect;
 
 #[derive(Clone)]
 pub struct Stencil {
 	bounds: Rect<i32, i32>,
 	mask: BitVec<Lsb0, u8>,
+	rank: Vec<u32>,
 	channel: Channel,
 	empty_pixel: Vec<u8>,
 	data: Vec<u8>,
 }
 
+/// Number of mask bits covered by each entry of the rank index
+const RANK_BLOCK: usize = 64;
+
+/// Count the set bits preceding each block of the mask, plus the total
+fn build_rank(mask: &BitVec<Lsb0, u8>) -> Vec<u32> {
+	let mut rank = Vec::with_capacity(mask.len() / RANK_BLOCK + 1);
+	let mut count = 0u32;
+	for block in mask.chunks(RANK_BLOCK) {
+		rank.push(count);
+		count += block.count_ones() as u32;
+	}
+	rank.push(count);
+	rank
+}
+
 impl Stencil {
 	/// Retrieve rectangle
 	pub fn bounds(&self) -> Rect<i32, i32> {
@@ -42,6 +60,7 @@ impl Stencil {
 	) -> Self {
 		Stencil {
 			bounds,
+			rank: build_rank(&mask),
 			mask,
 			channel,
 			empty_pixel: channel.default_pixel(),
@@ -60,6 +79,51 @@ impl Stencil {
 		Self::from_buffer(rect, channel, buffer)
 	}
 
+	/// Create a stencil by evaluating `pixel` at every coordinate of rect
+	///
+	/// `pixel` writes into its buffer and returns false to mask the pixel out.
+	/// Rows are evaluated in parallel when the `rayon` feature is enabled.
+	pub fn from_fn<F>(rect: Rect<i32, i32>, channel: Channel, pixel: F) -> Self
+	where
+		F: Fn(i32, i32, &mut [u8]) -> bool + Send + Sync,
+	{
+		let w = rect.w.max(0) as usize;
+		let default_pixel = channel.default_pixel();
+
+		#[cfg(feature = "rayon")]
+		let rows = (0..rect.h.max(0)).into_par_iter();
+		#[cfg(not(feature = "rayon"))]
+		let rows = 0..rect.h.max(0);
+
+		let rows = rows
+			.map(|y| {
+				let mut bits = vec![false; w];
+				let mut data: Vec<u8> = Vec::new();
+				let mut tmp = default_pixel.clone();
+				for (x, bit) in bits.iter_mut().enumerate() {
+					tmp.copy_from_slice(&default_pixel);
+					if pixel(rect.x + x as i32, rect.y + y, &mut tmp) {
+						*bit = true;
+						data.extend_from_slice(&tmp);
+					}
+				}
+				(bits, data)
+			})
+			.collect::<Vec<_>>();
+
+		let mut mask = bitvec![Lsb0, u8; 0; w * rect.h.max(0) as usize];
+		let mut data: Vec<u8> = Vec::with_capacity(rows.iter().map(|(_, data)| data.len()).sum());
+		for (y, (bits, row)) in rows.into_iter().enumerate() {
+			for (x, bit) in bits.into_iter().enumerate() {
+				if bit {
+					mask.set(y * w + x, true);
+				}
+			}
+			data.extend_from_slice(&row);
+		}
+		unsafe { Self::from_raw_parts(rect, mask, channel, data) }
+	}
+
 	/// Create a stencil from pixel data
 	pub fn from_buffer(rect: Rect<i32, i32>, channel: Channel, buffer: Vec<u8>) -> Self {
 		let len = (rect.w * rect.h) as usize;
@@ -76,38 +140,19 @@ impl Stencil {
 				let len = (rect.w * rect.h) as usize;
 				let stride = channel.pixel_stride();
 				assert_eq!(len * stride, buffer.len());
-				let mut mask = bitvec![Lsb0, u8; 0; len];
-				// #[cfg(feature = "rayon")]
-				// let chunks = buffer.par_chunks(stride);
-				// #[cfg(not(feature = "rayon"))]
-				let chunks = buffer.chunks(stride);
-
-				let data = chunks
-					.enumerate()
-					.filter_map(|(i, data)| {
-						let pixel = Pixel::from_buffer(&data, channel);
-						let alpha = match channel {
-							Channel::Lumaa | Channel::LumaaNormal => pixel.lumaa().unwrap().alpha,
-							Channel::Rgba | Channel::RgbaNormal => pixel.rgba().unwrap().alpha,
-							_ => 0,
-						};
-						if alpha == 0 {
-							None
-						} else {
-							mask.set(i, true);
-							Some(data.to_vec())
-						}
-					})
-					.flatten()
-					.collect::<Vec<_>>();
-
-				Self {
-					bounds: rect,
-					mask,
-					channel,
-					empty_pixel: channel.default_pixel(),
-					data,
-				}
+
+				Self::from_fn(rect, channel, |x, y, out| {
+					let i = ((y - rect.y) * rect.w + x - rect.x) as usize;
+					let data = &buffer[(i * stride)..((i + 1) * stride)];
+					let pixel = Pixel::from_buffer(data, channel);
+					let alpha = match channel {
+						Channel::Lumaa | Channel::LumaaNormal => pixel.lumaa().unwrap().alpha,
+						Channel::Rgba | Channel::RgbaNormal => pixel.rgba().unwrap().alpha,
+						_ => 0,
+					};
+					out.copy_from_slice(data);
+					alpha != 0
+				})
 			}
 			_ => Self::from_buffer(rect, channel, buffer),
 		}
@@ -133,13 +178,28 @@ impl Stencil {
 	pub fn try_index(&self, index: usize) -> Option<&[u8]> {
 		if self.mask[index] {
 			let stride = self.channel.pixel_stride();
-			let count: usize = self.mask[..index].count_ones();
+			let count = self.rank(index);
 			Some(&self.data[(count * stride)..((count + 1) * stride)])
 		} else {
 			None
 		}
 	}
 
+	/// Count the pixels present before index
+	pub fn rank(&self, index: usize) -> usize {
+		let block = index / RANK_BLOCK;
+		self.rank[block] as usize + self.mask[block * RANK_BLOCK..index].count_ones()
+	}
+
+	/// Verify that mask, rank index and data agree with the bounds
+	pub(crate) fn is_consistent(&self) -> bool {
+		self.bounds.w >= 0
+			&& self.bounds.h >= 0
+			&& self.mask.len() == (self.bounds.w * self.bounds.h) as usize
+			&& self.data.len() == self.mask.count_ones() * self.channel.pixel_stride()
+			&& self.rank == build_rank(&self.mask)
+	}
+
 	/// Merge two stencil and blend them together if need be
 	pub fn merge(frt: &Self, bck: &Self, blend_mode: Blending, compose_op: Compositing) -> Self {
 		assert_eq!(frt.channel, bck.channel);
@@ -148,33 +208,21 @@ impl Stencil {
 		// Calculate new size
 		let rect = frt.bounds.union(bck.bounds);
 
-		// Allocate new buffers
-		let stride = frt.channel.pixel_stride();
-		let mut mask = bitvec![Lsb0, u8; 0; (rect.w * rect.h) as usize];
-		let mut data: Vec<u8> = Vec::with_capacity((rect.w * rect.h * stride as i32) as usize);
-		let mut tmp = frt.channel.default_pixel();
-
-		for i in 0..mask.len() {
-			let x = (i % rect.w as usize) as i32 + rect.x;
-			let y = (i / rect.w as usize) as i32 + rect.y;
-
-			let frt_buf = frt.try_get(x, y);
-			let bck_buf = bck.try_get(x, y);
-
-			match (frt_buf, bck_buf) {
-				(None, None) => mask.set(i, false),
+		Self::from_fn(rect, channel, |x, y, out| {
+			match (frt.try_get(x, y), bck.try_get(x, y)) {
+				(None, None) => false,
 				(Some(frt_buf), None) => {
-					mask.set(i, true);
-					data.extend_from_slice(frt_buf);
+					out.copy_from_slice(frt_buf);
+					true
 				}
 				(None, Some(bck_buf)) => {
-					mask.set(i, true);
-					data.extend_from_slice(bck_buf);
+					out.copy_from_slice(bck_buf);
+					true
 				}
 				(Some(frt_buf), Some(bck_buf)) => {
-					let frt_px = Pixel::from_buffer(frt_buf, frt.channel);
-					let bck_px = Pixel::from_buffer(bck_buf, frt.channel);
-					let mut pixel = PixelMut::from_buffer_mut(&mut tmp, channel);
+					let frt_px = Pixel::from_buffer(frt_buf, channel);
+					let bck_px = Pixel::from_buffer(bck_buf, channel);
+					let mut pixel = PixelMut::from_buffer_mut(out, channel);
 					pixel
 						.blend(blend_mode, compose_op, &frt_px, &bck_px)
 						.unwrap();
@@ -183,20 +231,107 @@ impl Stencil {
 						Channel::Rgba | Channel::RgbaNormal => pixel.rgba().unwrap().alpha,
 						_ => 1,
 					};
-					if alpha > 0 {
-						mask.set(i, true);
-						data.extend_from_slice(&tmp);
+					alpha > 0
+				}
+			}
+		})
+	}
+
+	/// Copy the part of this stencil that lies within region
+	pub fn crop(&self, region: Rect<i32, i32>) -> Self {
+		let x0 = self.bounds.x.max(region.x);
+		let y0 = self.bounds.y.max(region.y);
+		let x1 = (self.bounds.x + self.bounds.w).min(region.x + region.w);
+		let y1 = (self.bounds.y + self.bounds.h).min(region.y + region.h);
+		let rect = Rect::new(x0, y0, (x1 - x0).max(0), (y1 - y0).max(0));
+
+		let stride = self.channel.pixel_stride();
+		let mut mask = bitvec![Lsb0, u8; 0; (rect.w * rect.h) as usize];
+		let mut data: Vec<u8> = Vec::new();
+		if rect.w > 0 && rect.h > 0 {
+			// Walk the covered rows once, keeping track of the data offset
+			let w = self.bounds.w as usize;
+			let start = (rect.y - self.bounds.y) as usize * w;
+			let end = (rect.y + rect.h - self.bounds.y) as usize * w;
+			let mut offset = self.rank(start);
+			for (i, bit) in self.mask[start..end].iter().enumerate() {
+				if *bit {
+					let x = ((start + i) % w) as i32 + self.bounds.x;
+					let y = ((start + i) / w) as i32 + self.bounds.y;
+					if x >= rect.x && x < rect.x + rect.w {
+						let index = ((y - rect.y) * rect.w + x - rect.x) as usize;
+						mask.set(index, true);
+						data.extend_from_slice(&self.data[offset * stride..(offset + 1) * stride]);
 					}
+					offset += 1;
 				}
 			}
 		}
-		Self {
-			bounds: rect,
-			mask,
-			channel,
-			empty_pixel: channel.default_pixel(),
-			data,
+		unsafe { Self::from_raw_parts(rect, mask, self.channel, data) }
+	}
+
+	/// Approximate heap memory used by this stencil, in bytes
+	pub fn heap_size(&self) -> usize {
+		self.mask.as_slice().len()
+			+ self.rank.len() * std::mem::size_of::<u32>()
+			+ self.data.len()
+			+ self.empty_pixel.len()
+	}
+
+	/// Returns true if pixel data has a non-zero alpha, or no alpha at all
+	pub(crate) fn is_visible(&self, data: &[u8]) -> bool {
+		let pixel = Pixel::from_buffer(data, self.channel);
+		match self.channel {
+			Channel::Lumaa | Channel::LumaaNormal => pixel.lumaa().unwrap().alpha > 0,
+			Channel::Rgba | Channel::RgbaNormal => pixel.rgba().unwrap().alpha > 0,
+			_ => true,
+		}
+	}
+
+	/// Smallest rect containing every visible pixel
+	///
+	/// Without visible pixels, this is an empty rect at the stencil's origin.
+	pub fn tight_bounds(&self) -> Rect<i32, i32> {
+		let mut min = (i32::MAX, i32::MAX);
+		let mut max = (i32::MIN, i32::MIN);
+		for (x, y, data) in self.iter() {
+			if self.is_visible(data) {
+				min = (min.0.min(x), min.1.min(y));
+				max = (max.0.max(x), max.1.max(y));
+			}
+		}
+		if min.0 > max.0 {
+			Rect::new(self.bounds.x, self.bounds.y, 0, 0)
+		} else {
+			Rect::new(min.0, min.1, max.0 - min.0 + 1, max.1 - min.1 + 1)
+		}
+	}
+
+	/// Shrink bounds to the visible pixels, keeping transparent pixels within
+	pub fn trim(&self) -> Self {
+		let rect = self.tight_bounds();
+		if rect.w == 0 {
+			return unsafe { Self::from_raw_parts(rect, BitVec::new(), self.channel, vec![]) };
+		}
+		self.crop(rect)
+	}
+
+	/// Drop fully transparent pixels and shrink bounds to the remaining ones
+	pub fn compact(&self) -> Self {
+		let rect = self.tight_bounds();
+		if rect.w == 0 {
+			return unsafe { Self::from_raw_parts(rect, BitVec::new(), self.channel, vec![]) };
+		}
+
+		let mut mask = bitvec![Lsb0, u8; 0; (rect.w * rect.h) as usize];
+		let mut data: Vec<u8> = Vec::with_capacity(self.data.len());
+		for (x, y, pixel) in self.iter() {
+			if self.is_visible(pixel) {
+				mask.set(((y - rect.y) * rect.w + x - rect.x) as usize, true);
+				data.extend_from_slice(pixel);
+			}
 		}
+		unsafe { Self::from_raw_parts(rect, mask, self.channel, data) }
 	}
 
 	/// Iterate over pixel of this stencil
@@ -211,6 +346,38 @@ impl Stencil {
 		}
 	}
 
+	/// Iterate over runs of contiguous pixels, row by row
+	pub fn spans(&self) -> StencilSpanIterator<'_> {
+		StencilSpanIterator {
+			bit_offset: 0,
+			bit_end: self.mask.len(),
+			data_offset: 0,
+			bounds: self.bounds,
+			mask: &self.mask,
+			pixel_stride: self.channel.pixel_stride(),
+			data: &self.data,
+		}
+	}
+
+	/// Iterate over runs of contiguous pixels in a single row
+	pub fn row_spans(&self, y: i32) -> StencilSpanIterator<'_> {
+		let (bit_offset, bit_end) = if y >= self.bounds.y && y < self.bounds.y + self.bounds.h {
+			let start = ((y - self.bounds.y) * self.bounds.w) as usize;
+			(start, start + self.bounds.w as usize)
+		} else {
+			(0, 0)
+		};
+		StencilSpanIterator {
+			bit_offset,
+			bit_end,
+			data_offset: self.rank(bit_offset),
+			bounds: self.bounds,
+			mask: &self.mask,
+			pixel_stride: self.channel.pixel_stride(),
+			data: &self.data,
+		}
+	}
+
 	/// Iterate over pixel of this stencil
 	pub fn iter_mut(&mut self) -> StencilMutIterator {
 		StencilMutIterator {
@@ -313,6 +480,47 @@ impl<'stencil> IntoIterator for &'stencil Stencil {
 	}
 }
 
+/// Iterator over `(y, x_start, x_end, data)` runs, `x_end` being exclusive
+pub struct StencilSpanIterator<'stencil> {
+	bit_offset: usize,
+	bit_end: usize,
+	data_offset: usize,
+	bounds: Rect<i32, i32>,
+	mask: &'stencil BitVec<Lsb0, u8>,
+	pixel_stride: usize,
+	data: &'stencil Vec<u8>,
+}
+
+impl<'stencil> Iterator for StencilSpanIterator<'stencil> {
+	type Item = (i32, i32, i32, &'stencil [u8]);
+
+	fn next(&mut self) -> Option<(i32, i32, i32, &'stencil [u8])> {
+		let w = self.bounds.w as usize;
+		while self.bit_offset < self.bit_end {
+			if !self.mask[self.bit_offset] {
+				self.bit_offset += 1;
+				continue;
+			}
+			// Extend the run until the end of the row or the first hole
+			let start = self.bit_offset;
+			let row_end = (start / w + 1) * w;
+			let mut end = start + 1;
+			while end < row_end && self.mask[end] {
+				end += 1;
+			}
+			let len = end - start;
+			let data = &self.data[self.data_offset * self.pixel_stride
+				..(self.data_offset + len) * self.pixel_stride];
+			self.bit_offset = end;
+			self.data_offset += len;
+			let x = (start % w) as i32 + self.bounds.x;
+			let y = (start / w) as i32 + self.bounds.y;
+			return Some((y, x, x + len as i32, data));
+		}
+		None
+	}
+}
+
 pub struct StencilMutIterator<'stencil> {
 	bit_offset: usize,
 	data_offset: usize,
@@ -452,6 +660,80 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn rank() {
+		let len = RANK_BLOCK * 3;
+		let buffer: Vec<u8> = (0..len)
+			.flat_map(|i| vec![i as u8, (i % 3) as u8])
+			.collect();
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, RANK_BLOCK as i32, 3),
+			Channel::Lumaa,
+			buffer,
+		);
+		for index in 0..=len {
+			assert_eq!(a.rank(index), a.mask[..index].count_ones());
+		}
+		assert_eq!(a.try_index(len - 1), Some(&[(len - 1) as u8, 2][..]));
+		assert_eq!(a.try_index(len - 2), Some(&[(len - 2) as u8, 1][..]));
+		assert_eq!(a.try_index(len - 3), None);
+	}
+
+	#[test]
+	fn crop() {
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 3, 2),
+			Channel::Lumaa,
+			vec![1, 255, 0, 0, 3, 255, 4, 255, 5, 255, 0, 0],
+		);
+		let b = a.crop(Rect::new(1, -1, 5, 5));
+		assert_eq!(b.bounds(), Rect::new(1, 0, 2, 2));
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠊ )");
+		assert_eq!(b.data, vec![3, 255, 5, 255]);
+		let b = a.crop(Rect::new(10, 10, 5, 5));
+		assert_eq!(b.bounds().w * b.bounds().h, 0);
+		assert_eq!(b.data.len(), 0);
+	}
+
+	#[test]
+	fn compact() {
+		let a = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 3),
+			Channel::Lumaa,
+			vec![0, 0, 0, 0, 0, 0, 0, 0, 5, 255, 6, 255, 0, 0, 7, 0, 8, 255],
+		);
+		let b = a.compact();
+		assert_eq!(b.bounds(), Rect::new(1, 1, 2, 2));
+		assert_eq!(format!("{:?}", b), "Stencil ( ⠙ )");
+		assert_eq!(b.data, vec![5, 255, 6, 255, 8, 255]);
+		assert!(b.heap_size() < a.heap_size());
+
+		let a = Stencil::new(Rect::new(4, 4, 2, 2), Channel::Lumaa);
+		let b = a.compact();
+		assert_eq!(b.bounds(), Rect::new(4, 4, 0, 0));
+		assert_eq!(b.data.len(), 0);
+
+		let a = Stencil::new(Rect::new(4, 4, 2, 2), Channel::Luma);
+		assert_eq!(a.compact().bounds(), a.bounds());
+	}
+
+	#[test]
+	fn trim() {
+		let a = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 3),
+			Channel::Lumaa,
+			vec![0, 0, 0, 0, 0, 0, 0, 0, 5, 255, 6, 0, 0, 0, 7, 0, 8, 255],
+		);
+		assert_eq!(a.tight_bounds(), Rect::new(1, 1, 2, 2));
+		let b = a.trim();
+		assert_eq!(b.bounds(), Rect::new(1, 1, 2, 2));
+		assert_eq!(b.data, vec![5, 255, 6, 0, 7, 0, 8, 255]);
+
+		let a = Stencil::new(Rect::new(4, 4, 2, 2), Channel::Rgba);
+		assert_eq!(a.tight_bounds(), Rect::new(4, 4, 0, 0));
+		assert_eq!(a.trim().data.len(), 0);
+	}
+
 	#[test]
 	fn iter() {
 		let a = Stencil::from_buffer(
@@ -479,6 +761,31 @@ mod tests {
 		assert_eq!(pixels, vec![1, 255, 4, 255]);
 	}
 
+	#[test]
+	fn spans() {
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(1, 2, 4, 2),
+			Channel::Lumaa,
+			vec![1, 255, 2, 255, 0, 0, 4, 255, 5, 255, 6, 255, 7, 255, 8, 255],
+		);
+		let spans: Vec<_> = a.spans().collect();
+		assert_eq!(
+			spans,
+			vec![
+				(2, 1, 3, &[1, 255, 2, 255][..]),
+				(2, 4, 5, &[4, 255][..]),
+				(3, 1, 5, &[5, 255, 6, 255, 7, 255, 8, 255][..]),
+			]
+		);
+		let spans: Vec<_> = a.row_spans(3).collect();
+		assert_eq!(
+			spans,
+			vec![(3, 1, 5, &[5, 255, 6, 255, 7, 255, 8, 255][..])]
+		);
+		assert_eq!(a.row_spans(4).count(), 0);
+		assert_eq!(a.row_spans(1).count(), 0);
+	}
+
 	#[test]
 	fn iter_mut() {
 		let mut a = Stencil::from_buffer(
@@ -505,4 +812,64 @@ mod tests {
 			.collect();
 		assert_eq!(pixels, vec![1, 255, 4, 255]);
 	}
+
+	/// Pseudo-random Rgba pixels, a third of them fully transparent
+	fn noise_buffer(rect: Rect<i32, i32>, seed: u32) -> Vec<u8> {
+		let mut state = seed;
+		let mut buffer = Vec::with_capacity((rect.w * rect.h * 4) as usize);
+		for _ in 0..(rect.w * rect.h) {
+			state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+			let [r, g, b, a] = state.to_le_bytes();
+			buffer.extend_from_slice(&[r, g, b, if a % 3 == 0 { 0 } else { a | 1 }]);
+		}
+		buffer
+	}
+
+	#[test]
+	fn parallel_matches_serial() {
+		// Run with and without the `rayon` feature, both must match these
+		// pixel by pixel references byte for byte
+		let rect = Rect::new(-30, -10, 150, 70);
+		let buffer = noise_buffer(rect, 7);
+		let a = Stencil::from_buffer_mask_alpha(rect, Channel::Rgba, buffer.clone());
+		let mut mask = bitvec![Lsb0, u8; 0; (rect.w * rect.h) as usize];
+		let mut data = Vec::new();
+		for (i, pixel) in buffer.chunks(4).enumerate() {
+			if pixel[3] > 0 {
+				mask.set(i, true);
+				data.extend_from_slice(pixel);
+			}
+		}
+		assert_eq!(*a.mask, mask);
+		assert_eq!(*a.data, data);
+
+		let b_rect = Rect::new(40, 20, 120, 90);
+		let b = Stencil::from_buffer_mask_alpha(b_rect, Channel::Rgba, noise_buffer(b_rect, 11));
+		let merged = Stencil::merge(&a, &b, Blending::Normal, Compositing::Lighter);
+		let union = rect.union(b_rect);
+		assert_eq!(merged.bounds(), union);
+		for y in union.y..union.y + union.h {
+			for x in union.x..union.x + union.w {
+				let pixel = Rect::new(x, y, 1, 1);
+				let expected = Stencil::merge(
+					&a.crop(pixel),
+					&b.crop(pixel),
+					Blending::Normal,
+					Compositing::Lighter,
+				);
+				assert_eq!(merged.try_get(x, y), expected.try_get(x, y));
+			}
+		}
+
+		let canvas = crate::Canvas::from_stencil(b).apply_stencil(a).unwrap();
+		let copy = canvas.copy_to_stencil();
+		let mut data = Vec::new();
+		for y in union.y..union.y + union.h {
+			for x in union.x..union.x + union.w {
+				data.extend_from_slice(&canvas[(x, y)]);
+			}
+		}
+		assert_eq!(copy.bounds(), union);
+		assert_eq!(*copy.data, data);
+	}
 }
@@ -0,0 +1,435 @@
+use crate::{check_channel, write_color, Stencil};
+use bitvec::{bitvec, order::Lsb0, vec::BitVec};
+use color::{Alpha, Channel, ChannelError, Rgb, Rgba};
+use std::{collections::HashMap, convert::TryFrom};
+use vek::{geom::repr_c::Rect, vec::repr_c::vec2::Vec2};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FontError {
+	/// Malformed BDF data, at a 1-based line number
+	Syntax(usize),
+	NoGlyphs,
+}
+
+impl std::error::Error for FontError {}
+
+impl std::fmt::Display for FontError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			FontError::Syntax(line) => write!(f, "Invalid font syntax at line {}.", line),
+			FontError::NoGlyphs => write!(f, "Font has no glyph."),
+		}
+	}
+}
+
+/// Horizontal alignment of the lines of a text block
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TextAlign {
+	Left,
+	Center,
+	Right,
+}
+
+/// How text is laid out and colored
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TextStyle {
+	pub color: Rgba,
+	/// Extra pixels between glyphs, may be negative
+	pub letter_spacing: i32,
+	/// Distance between baselines, defaults to the font's line height
+	pub line_height: Option<i32>,
+	pub align: TextAlign,
+}
+
+impl Default for TextStyle {
+	fn default() -> Self {
+		TextStyle {
+			color: Alpha::new(Rgb::new(0, 0, 0), 255),
+			letter_spacing: 0,
+			line_height: None,
+			align: TextAlign::Left,
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Glyph {
+	/// Pixels relative to the pen position on the baseline
+	bounds: Rect<i32, i32>,
+	advance: i32,
+	bits: BitVec<Lsb0, u8>,
+}
+
+/// Monochrome font made of pixel glyphs
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitmapFont {
+	glyphs: HashMap<char, Glyph>,
+	ascent: i32,
+	descent: i32,
+}
+
+impl BitmapFont {
+	/// Parse a font in the Glyph Bitmap Distribution Format
+	pub fn from_bdf(source: &str) -> Result<Self, FontError> {
+		let mut glyphs = HashMap::new();
+		let mut ascent: Option<i32> = None;
+		let mut descent: Option<i32> = None;
+		let mut bounding_box: Option<(i32, i32)> = None;
+
+		let mut encoding: Option<u32> = None;
+		let mut advance = 0;
+		let mut bounds = Rect::new(0, 0, 0, 0);
+		let mut rows: Option<Vec<&str>> = None;
+
+		for (number, line) in source.lines().enumerate() {
+			let error = FontError::Syntax(number + 1);
+			let line = line.trim();
+			if let Some(rows) = rows.as_mut() {
+				if line != "ENDCHAR" {
+					rows.push(line);
+					continue;
+				}
+			}
+			let mut words = line.split_whitespace();
+			let keyword = words.next().unwrap_or("");
+			let values: Vec<i32> = words.filter_map(|word| word.parse().ok()).collect();
+			let value = |i: usize| values.get(i).copied().ok_or_else(|| error.clone());
+			match keyword {
+				"FONTBOUNDINGBOX" => bounding_box = Some((value(1)?, value(3)?)),
+				"FONT_ASCENT" => ascent = Some(value(0)?),
+				"FONT_DESCENT" => descent = Some(value(0)?),
+				"STARTCHAR" => {
+					encoding = None;
+					advance = 0;
+					bounds = Rect::new(0, 0, 0, 0);
+				}
+				"ENCODING" => encoding = u32::try_from(value(0)?).ok(),
+				"DWIDTH" => advance = value(0)?,
+				"BBX" => {
+					let (w, h) = (value(0)?, value(1)?);
+					if w < 0 || h < 0 {
+						return Err(error);
+					}
+					bounds = Rect::new(value(2)?, -(value(3)? + h), w, h);
+				}
+				"BITMAP" => rows = Some(vec![]),
+				"ENDCHAR" => {
+					let rows = rows.take().ok_or_else(|| error.clone())?;
+					if rows.len() != bounds.h as usize {
+						return Err(error);
+					}
+					let w = bounds.w as usize;
+					let mut bits = bitvec![Lsb0, u8; 0; w * rows.len()];
+					for (y, row) in rows.iter().enumerate() {
+						for x in 0..w {
+							let digit = row
+								.get(x / 4..x / 4 + 1)
+								.and_then(|digit| u8::from_str_radix(digit, 16).ok())
+								.ok_or_else(|| error.clone())?;
+							if digit & (0x8 >> (x % 4)) != 0 {
+								bits.set(y * w + x, true);
+							}
+						}
+					}
+					if let Some(c) = encoding.and_then(std::char::from_u32) {
+						glyphs.insert(
+							c,
+							Glyph {
+								bounds,
+								advance,
+								bits,
+							},
+						);
+					}
+				}
+				_ => {}
+			}
+		}
+
+		if glyphs.is_empty() {
+			return Err(FontError::NoGlyphs);
+		}
+		let (ascent, descent) = match (ascent, descent, bounding_box) {
+			(Some(ascent), Some(descent), _) => (ascent, descent),
+			(_, _, Some((h, y))) => (h + y, -y),
+			_ => return Err(FontError::Syntax(source.lines().count())),
+		};
+		Ok(BitmapFont {
+			glyphs,
+			ascent,
+			descent,
+		})
+	}
+
+	/// Cut a monospace font out of a sheet of `cell` sized glyphs
+	///
+	/// Glyphs for `chars` are read row by row from the top-left of the sheet;
+	/// masked-in pixels are set.
+	pub fn from_grid(sheet: &Stencil, cell: Vec2<i32>, chars: &str) -> Self {
+		assert!(cell.x > 0 && cell.y > 0);
+		let sheet_bounds = sheet.bounds();
+		let columns = (sheet_bounds.w / cell.x).max(1);
+		let cells = columns * (sheet_bounds.h / cell.y);
+		let mut glyphs = HashMap::new();
+		for (i, c) in chars.chars().enumerate().take(cells.max(0) as usize) {
+			let left = sheet_bounds.x + (i as i32 % columns) * cell.x;
+			let top = sheet_bounds.y + (i as i32 / columns) * cell.y;
+			let mut bits = bitvec![Lsb0, u8; 0; (cell.x * cell.y) as usize];
+			for y in 0..cell.y {
+				for x in 0..cell.x {
+					if sheet.try_get(left + x, top + y).is_some() {
+						bits.set((y * cell.x + x) as usize, true);
+					}
+				}
+			}
+			glyphs.insert(
+				c,
+				Glyph {
+					bounds: Rect::new(0, -cell.y, cell.x, cell.y),
+					advance: cell.x,
+					bits,
+				},
+			);
+		}
+		BitmapFont {
+			glyphs,
+			ascent: cell.y,
+			descent: 0,
+		}
+	}
+
+	/// Pixels above the baseline
+	pub fn ascent(&self) -> i32 {
+		self.ascent
+	}
+
+	/// Pixels below the baseline
+	pub fn descent(&self) -> i32 {
+		self.descent
+	}
+
+	/// Default distance between baselines
+	pub fn line_height(&self) -> i32 {
+		self.ascent + self.descent
+	}
+
+	/// Returns true if the font can draw a character
+	pub fn has_glyph(&self, c: char) -> bool {
+		self.glyphs.contains_key(&c)
+	}
+
+	/// Glyph drawn for a character, falling back to `?`
+	fn glyph(&self, c: char) -> Option<&Glyph> {
+		self.glyphs.get(&c).or_else(|| self.glyphs.get(&'?'))
+	}
+
+	/// Width of a single line of text
+	fn line_width(&self, line: &str, letter_spacing: i32) -> i32 {
+		let (width, count) = line
+			.chars()
+			.fold((0, 0), |(width, count), c| match self.glyph(c) {
+				Some(glyph) => (width + glyph.advance, count + 1),
+				None => (width, count),
+			});
+		width + letter_spacing * (count - 1).max(0)
+	}
+
+	/// Size of the text block in pixels
+	pub fn measure(&self, text: &str, style: &TextStyle) -> Vec2<i32> {
+		let line_height = style.line_height.unwrap_or_else(|| self.line_height());
+		let lines = text.split('\n').count() as i32;
+		let width = text
+			.split('\n')
+			.map(|line| self.line_width(line, style.letter_spacing))
+			.max()
+			.unwrap_or(0);
+		Vec2::new(width, line_height * (lines - 1) + self.line_height())
+	}
+
+	/// Render text with the top-left corner of its block at position
+	///
+	/// Lines are separated by `\n` and aligned within the widest line.
+	pub fn render(
+		&self,
+		text: &str,
+		position: Vec2<i32>,
+		channel: Channel,
+		style: &TextStyle,
+	) -> Result<Stencil, ChannelError> {
+		check_channel(channel)?;
+		let line_height = style.line_height.unwrap_or_else(|| self.line_height());
+		let block = self.measure(text, style);
+
+		// Collect set pixels and their extent
+		let mut pixels: Vec<(i32, i32)> = vec![];
+		for (i, line) in text.split('\n').enumerate() {
+			let width = self.line_width(line, style.letter_spacing);
+			let mut pen = position.x
+				+ match style.align {
+					TextAlign::Left => 0,
+					TextAlign::Center => (block.x - width) / 2,
+					TextAlign::Right => block.x - width,
+				};
+			let baseline = position.y + self.ascent + line_height * i as i32;
+			for glyph in line.chars().filter_map(|c| self.glyph(c)) {
+				for (index, bit) in glyph.bits.iter().enumerate() {
+					if *bit {
+						pixels.push((
+							pen + glyph.bounds.x + (index as i32 % glyph.bounds.w),
+							baseline + glyph.bounds.y + (index as i32 / glyph.bounds.w),
+						));
+					}
+				}
+				pen += glyph.advance + style.letter_spacing;
+			}
+		}
+		if pixels.is_empty() {
+			return Ok(Stencil::new(
+				Rect::new(position.x, position.y, 0, 0),
+				channel,
+			));
+		}
+
+		let (mut min, mut max) = (pixels[0], pixels[0]);
+		for (x, y) in pixels.iter() {
+			min = (min.0.min(*x), min.1.min(*y));
+			max = (max.0.max(*x), max.1.max(*y));
+		}
+		let rect = Rect::new(min.0, min.1, max.0 - min.0 + 1, max.1 - min.1 + 1);
+		let mut grid = bitvec![Lsb0, u8; 0; (rect.w * rect.h) as usize];
+		for (x, y) in pixels {
+			grid.set(((y - rect.y) * rect.w + x - rect.x) as usize, true);
+		}
+		Ok(Stencil::from_fn(rect, channel, |x, y, pixel| {
+			if grid[((y - rect.y) * rect.w + x - rect.x) as usize] {
+				write_color(channel, style.color, pixel);
+				true
+			} else {
+				false
+			}
+		}))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FONT: &str = "STARTFONT 2.1
+FONT -test-fixed-medium-r-normal--4-40-75-75-c-30-iso10646-1
+SIZE 4 75 75
+FONTBOUNDINGBOX 3 4 0 -1
+STARTPROPERTIES 2
+FONT_ASCENT 3
+FONT_DESCENT 1
+ENDPROPERTIES
+CHARS 3
+STARTCHAR A
+ENCODING 65
+SWIDTH 750 0
+DWIDTH 4 0
+BBX 3 3 0 0
+BITMAP
+40
+A0
+E0
+ENDCHAR
+STARTCHAR period
+ENCODING 46
+SWIDTH 250 0
+DWIDTH 2 0
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+STARTCHAR unmapped
+ENCODING -1
+DWIDTH 2 0
+BBX 1 1 0 0
+BITMAP
+80
+ENDCHAR
+ENDFONT
+";
+
+	#[test]
+	fn parse_bdf() {
+		let font = BitmapFont::from_bdf(FONT).unwrap();
+		assert_eq!(font.ascent(), 3);
+		assert_eq!(font.descent(), 1);
+		assert!(font.has_glyph('A'));
+		assert!(font.has_glyph('.'));
+		assert!(!font.has_glyph('B'));
+		assert_eq!(font.glyphs.len(), 2);
+
+		assert_eq!(
+			BitmapFont::from_bdf("STARTFONT 2.1\nENDFONT\n"),
+			Err(FontError::NoGlyphs)
+		);
+		assert_eq!(
+			BitmapFont::from_bdf(&FONT.replace("A0\nE0", "A0")),
+			Err(FontError::Syntax(18))
+		);
+	}
+
+	#[test]
+	fn render() {
+		let font = BitmapFont::from_bdf(FONT).unwrap();
+		let style = TextStyle::default();
+		assert_eq!(font.measure("A.A", &style), Vec2::new(10, 4));
+
+		let a = font
+			.render("A.", Vec2::new(10, 20), Channel::Luma, &style)
+			.unwrap();
+		assert_eq!(a.bounds(), Rect::new(10, 20, 5, 3));
+		assert_eq!(format!("{:?}", a), "Stencil ( ⠮⠆⠄ )");
+		assert_eq!(a.try_get(11, 20), Some(&[0][..]));
+
+		let style = TextStyle {
+			color: Alpha::new(Rgb::new(9, 9, 9), 128),
+			letter_spacing: 1,
+			line_height: Some(5),
+			align: TextAlign::Right,
+		};
+		let a = font
+			.render(".\nA", Vec2::new(0, 0), Channel::Lumaa, &style)
+			.unwrap();
+		assert_eq!(a.bounds(), Rect::new(0, 2, 3, 6));
+		assert_eq!(a.try_get(2, 2), Some(&[9, 128][..]));
+		assert_eq!(a.try_get(1, 5), Some(&[9, 128][..]));
+
+		let a = font
+			.render("", Vec2::new(3, 3), Channel::Rgba, &style)
+			.unwrap();
+		assert_eq!(a.bounds(), Rect::new(3, 3, 0, 0));
+		assert!(font
+			.render("A", Vec2::new(0, 0), Channel::Normal, &style)
+			.is_err());
+	}
+
+	#[test]
+	fn grid_font() {
+		// Two 2x2 glyphs side by side: a full block and a diagonal
+		let sheet = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 4, 2),
+			Channel::Lumaa,
+			vec![
+				1, 255, 1, 255, 1, 255, 0, 0, //
+				1, 255, 1, 255, 0, 0, 1, 255,
+			],
+		);
+		let font = BitmapFont::from_grid(&sheet, Vec2::new(2, 2), "#\\x");
+		assert!(font.has_glyph('#'));
+		assert!(font.has_glyph('\\'));
+		assert!(!font.has_glyph('x'));
+		assert_eq!(font.line_height(), 2);
+
+		let a = font
+			.render("\\#", Vec2::new(0, 0), Channel::Luma, &TextStyle::default())
+			.unwrap();
+		assert_eq!(a.bounds(), Rect::new(0, 0, 4, 2));
+		assert_eq!(a.try_get(1, 0), None);
+		assert_eq!(a.try_get(1, 1), Some(&[0][..]));
+		assert_eq!(a.data().len(), 6);
+	}
+}
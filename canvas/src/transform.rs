@@ -1,24 +1,132 @@
-use crate::{Canvas, Samplable, Sampling, Stencil};
+use crate::{component_layout, Addressing, Canvas, Samplable, Sampling, Stencil};
 use bitvec::{bitvec, order::Lsb0};
-use color::ChannelError;
+use color::{Channel, ChannelError};
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
+use std::collections::HashMap;
 use vek::{
 	geom::repr_c::{Aabr, Rect},
 	mat::repr_c::column_major::{Mat3, Mat4},
-	vec::repr_c::vec2::Vec2,
+	vec::repr_c::{vec2::Vec2, vec3::Vec3},
 };
 
 pub trait Transformable {
 	type Output;
 
-	fn transform(&self, sampling: Sampling, matrix: &Mat3<f32>) -> Self::Output;
+	/// Transform with a matrix, sampling outside of bounds as transparent
+	fn transform(&self, sampling: Sampling, matrix: &Mat3<f32>) -> Self::Output {
+		self.transform_with_addressing(sampling, Addressing::Border, matrix)
+	}
+
+	/// Transform with a matrix, sampling outside of bounds with an addressing mode
+	fn transform_with_addressing(
+		&self,
+		sampling: Sampling,
+		addressing: Addressing,
+		matrix: &Mat3<f32>,
+	) -> Self::Output;
+
+	/// Transform with an affine or projective matrix around a pivot in document space
+	///
+	/// The result covers `transformed_bounds(bounds, pivot, matrix)`.
+	fn transform_around(
+		&self,
+		sampling: Sampling,
+		addressing: Addressing,
+		pivot: Vec2<f32>,
+		matrix: &Mat3<f32>,
+	) -> Self::Output;
+}
+
+/// Map a point in document space by a matrix around a pivot
+///
+/// Returns `None` when a projective matrix sends the point behind the viewer.
+pub fn transform_point(
+	matrix: &Mat3<f32>,
+	pivot: Vec2<f32>,
+	point: Vec2<f32>,
+) -> Option<Vec2<f32>> {
+	let v = *matrix * Vec3::new(point.x - pivot.x, point.y - pivot.y, 1.);
+	if v.z <= f32::EPSILON {
+		None
+	} else {
+		Some(Vec2::new(v.x / v.z + pivot.x, v.y / v.z + pivot.y))
+	}
+}
+
+/// Destination rect of bounds transformed by a matrix around a pivot
+///
+/// Returns an empty rect at the pivot if any corner can not be projected.
+pub fn transformed_bounds(
+	bounds: Rect<i32, i32>,
+	pivot: Vec2<f32>,
+	matrix: &Mat3<f32>,
+) -> Rect<i32, i32> {
+	let (x, y, w, h) = (
+		bounds.x as f32,
+		bounds.y as f32,
+		bounds.w as f32,
+		bounds.h as f32,
+	);
+	let corners = [
+		Vec2::new(x, y),
+		Vec2::new(x + w, y),
+		Vec2::new(x, y + h),
+		Vec2::new(x + w, y + h),
+	];
+	let mut l = f32::MAX;
+	let mut t = f32::MAX;
+	let mut r = f32::MIN;
+	let mut b = f32::MIN;
+	for corner in corners.iter() {
+		match transform_point(matrix, pivot, *corner) {
+			Some(p) => {
+				l = l.min(p.x);
+				t = t.min(p.y);
+				r = r.max(p.x);
+				b = b.max(p.y);
+			}
+			None => return Rect::new(pivot.x as i32, pivot.y as i32, 0, 0),
+		}
+	}
+	// Absorb floating point error so exact transforms keep exact bounds
+	let l = (l + 1e-3).floor() as i32;
+	let t = (t + 1e-3).floor() as i32;
+	let r = (r - 1e-3).ceil() as i32;
+	let b = (b - 1e-3).ceil() as i32;
+	Rect::new(l, t, (r - l).max(0), (b - t).max(0))
+}
+
+/// Addressing mode to sample a point in source space with, if it is covered
+///
+/// Points are covered when they fall inside bounds or when addressing repeats
+/// the source outside of them.
+fn cover(bounds: Rect<i32, i32>, point: Vec2<f32>, addressing: Addressing) -> Option<Addressing> {
+	let inside = point.x >= bounds.x as f32
+		&& point.y >= bounds.y as f32
+		&& point.x < (bounds.x + bounds.w) as f32
+		&& point.y < (bounds.y + bounds.h) as f32;
+	match addressing {
+		Addressing::Border if inside => Some(Addressing::Clamp),
+		Addressing::Border => None,
+		addressing => Some(addressing),
+	}
+}
+
+/// Invert a matrix, projective or not
+fn inverted(matrix: &Mat3<f32>) -> Mat3<f32> {
+	Into::<Mat3<f32>>::into(Into::<Mat4<f32>>::into(*matrix).inverted())
 }
 
 impl Transformable for Canvas {
 	type Output = Result<Canvas, ChannelError>;
 
-	fn transform(&self, sampling: Sampling, matrix: &Mat3<f32>) -> Result<Canvas, ChannelError> {
+	fn transform_with_addressing(
+		&self,
+		sampling: Sampling,
+		addressing: Addressing,
+		matrix: &Mat3<f32>,
+	) -> Result<Canvas, ChannelError> {
 		let channel = self.channel();
 		let stride = channel.pixel_stride();
 		let old_bounds = self.bounds();
@@ -67,7 +175,53 @@ impl Transformable for Canvas {
 		chunks.enumerate().for_each(|(y, row)| {
 			for (x, slice) in row.chunks_mut(stride).enumerate() {
 				let pos = projection.mul_point_2d(Vec2::new(x as f32, y as f32));
-				let _ = self.sample2d((pos.x, pos.y), sampling, slice);
+				let _ = self.sample2d(
+					(pos.x + old_bounds.x as f32, pos.y + old_bounds.y as f32),
+					sampling,
+					addressing,
+					slice,
+				);
+			}
+		});
+		Ok(Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			new_bounds, channel, data,
+		)))
+	}
+
+	fn transform_around(
+		&self,
+		sampling: Sampling,
+		addressing: Addressing,
+		pivot: Vec2<f32>,
+		matrix: &Mat3<f32>,
+	) -> Result<Canvas, ChannelError> {
+		let channel = self.channel();
+		let stride = channel.pixel_stride();
+		let old_bounds = self.bounds();
+		let new_bounds = transformed_bounds(old_bounds, pivot, matrix);
+		let inverse = inverted(matrix);
+
+		let mut data: Vec<u8> = vec![0u8; new_bounds.w as usize * new_bounds.h as usize * stride];
+		let pitch = (new_bounds.w as usize * stride).max(1);
+
+		#[cfg(feature = "rayon")]
+		let chunks = data.par_chunks_mut(pitch);
+		#[cfg(not(feature = "rayon"))]
+		let chunks = data.chunks_mut(pitch);
+
+		chunks.enumerate().for_each(|(y, row)| {
+			for (x, slice) in row.chunks_mut(stride).enumerate() {
+				// Map the destination pixel center back into source space
+				let center = Vec2::new(
+					(new_bounds.x + x as i32) as f32 + 0.5,
+					(new_bounds.y + y as i32) as f32 + 0.5,
+				);
+				if let Some(pos) = transform_point(&inverse, pivot, center) {
+					if let Some(addressing) = cover(old_bounds, pos, addressing) {
+						let _ =
+							self.sample2d((pos.x - 0.5, pos.y - 0.5), sampling, addressing, slice);
+					}
+				}
 			}
 		});
 		Ok(Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
@@ -79,9 +233,13 @@ impl Transformable for Canvas {
 impl Transformable for Stencil {
 	type Output = Result<Stencil, ChannelError>;
 
-	fn transform(&self, sampling: Sampling, matrix: &Mat3<f32>) -> Result<Stencil, ChannelError> {
+	fn transform_with_addressing(
+		&self,
+		sampling: Sampling,
+		addressing: Addressing,
+		matrix: &Mat3<f32>,
+	) -> Result<Stencil, ChannelError> {
 		let channel = self.channel();
-		let stride = channel.pixel_stride();
 		let old_bounds = self.bounds();
 
 		// Calculate new bounds
@@ -117,25 +275,433 @@ impl Transformable for Stencil {
 		// Invert matrix
 		let projection = Into::<Mat3<f32>>::into(Into::<Mat4<f32>>::into(projection).inverted());
 
-		let len = new_bounds.w as usize * new_bounds.h as usize;
-		let mut data: Vec<u8> = Vec::with_capacity(len * stride);
-		let mut mask = bitvec![Lsb0, u8; 0; len];
-		let mut tmp = channel.default_pixel();
+		Ok(Stencil::from_fn(new_bounds, channel, |x, y, out| {
+			let pos = projection.mul_point_2d(Vec2::new(
+				(x - new_bounds.x) as f32,
+				(y - new_bounds.y) as f32,
+			));
+			self.sample2d(
+				(pos.x + old_bounds.x as f32, pos.y + old_bounds.y as f32),
+				sampling,
+				addressing,
+				out,
+			)
+			.is_ok()
+		}))
+	}
 
-		for y in 0..(new_bounds.h as usize) {
-			for x in 0..(new_bounds.w as usize) {
-				let pos = projection.mul_point_2d(Vec2::new(x as f32, y as f32));
-				if let Ok(()) = self.sample2d((pos.x, pos.y), sampling, &mut tmp) {
-					let index = ((y as i64).wrapping_sub(new_bounds.y as i64) * new_bounds.w as i64
-						+ (x as i64).wrapping_sub(new_bounds.x as i64)) as usize;
-					mask.set(index, true);
-					data.extend_from_slice(&tmp);
+	fn transform_around(
+		&self,
+		sampling: Sampling,
+		addressing: Addressing,
+		pivot: Vec2<f32>,
+		matrix: &Mat3<f32>,
+	) -> Result<Stencil, ChannelError> {
+		let channel = self.channel();
+		let old_bounds = self.bounds();
+		let new_bounds = transformed_bounds(old_bounds, pivot, matrix);
+		let inverse = inverted(matrix);
+
+		Ok(Stencil::from_fn(new_bounds, channel, |x, y, out| {
+			// Map the destination pixel center back into source space
+			let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+			let pos = match transform_point(&inverse, pivot, center) {
+				Some(pos) => pos,
+				None => return false,
+			};
+			match cover(old_bounds, pos, addressing) {
+				Some(addressing) => self
+					.sample2d((pos.x - 0.5, pos.y - 0.5), sampling, addressing, out)
+					.is_ok(),
+				None => false,
+			}
+		}))
+	}
+}
+
+/// Pixel-art aware upscaling algorithms
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Upscaling {
+	/// AdvMAME2x, doubles the size
+	Scale2x,
+	/// AdvMAME3x, triples the size
+	Scale3x,
+	/// Eric's Pixel Expansion, doubles the size
+	Epx,
+	/// 2xBR without blending so that no new color is introduced, doubles the size
+	Xbr,
+}
+
+impl Upscaling {
+	/// Scaling factor of this algorithm
+	pub fn factor(&self) -> usize {
+		match self {
+			Upscaling::Scale2x | Upscaling::Epx | Upscaling::Xbr => 2,
+			Upscaling::Scale3x => 3,
+		}
+	}
+}
+
+pub trait PixelArtTransformable {
+	type Output;
+
+	/// Upscale without introducing new colors
+	fn upscale(&self, upscaling: Upscaling) -> Self::Output;
+
+	/// Rotate by an arbitrary angle (in radians) using RotSprite
+	fn rotsprite(&self, angle: f32) -> Self::Output;
+}
+
+impl PixelArtTransformable for Canvas {
+	type Output = Canvas;
+
+	fn upscale(&self, upscaling: Upscaling) -> Canvas {
+		let stencil = self.copy_to_stencil();
+		let (palette, grid) = IndexedGrid::from_stencil(&stencil);
+		let grid = grid.upscale(upscaling, &palette, stencil.channel());
+		canvas_from_grid(&grid, &palette, stencil.channel(), stencil.bounds())
+	}
+
+	fn rotsprite(&self, angle: f32) -> Canvas {
+		let stencil = self.copy_to_stencil();
+		let (palette, grid) = IndexedGrid::from_stencil(&stencil);
+		let grid = grid.rotsprite(angle);
+		canvas_from_grid(&grid, &palette, stencil.channel(), stencil.bounds())
+	}
+}
+
+impl PixelArtTransformable for Stencil {
+	type Output = Stencil;
+
+	fn upscale(&self, upscaling: Upscaling) -> Stencil {
+		let (palette, grid) = IndexedGrid::from_stencil(self);
+		grid.upscale(upscaling, &palette, self.channel())
+			.into_stencil(&palette, self.channel(), self.bounds())
+	}
+
+	fn rotsprite(&self, angle: f32) -> Stencil {
+		let (palette, grid) = IndexedGrid::from_stencil(self);
+		grid.rotsprite(angle)
+			.into_stencil(&palette, self.channel(), self.bounds())
+	}
+}
+
+fn canvas_from_grid(
+	grid: &IndexedGrid,
+	palette: &[&[u8]],
+	channel: Channel,
+	bounds: Rect<i32, i32>,
+) -> Canvas {
+	let empty = channel.default_pixel();
+	let mut data = Vec::with_capacity(grid.cells.len() * channel.pixel_stride());
+	for index in grid.cells.iter() {
+		match *index {
+			EMPTY_INDEX => data.extend_from_slice(&empty),
+			index => data.extend_from_slice(palette[index as usize]),
+		}
+	}
+	Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+		Rect::new(bounds.x, bounds.y, grid.width as i32, grid.height as i32),
+		channel,
+		data,
+	))
+}
+
+const EMPTY_INDEX: u32 = u32::MAX;
+
+/// Dense grid of indices into a palette of unique pixels
+///
+/// Pixel-art algorithms compare pixels for equality, comparing indices
+/// instead of pixel data keeps them cheap and channel agnostic.
+struct IndexedGrid {
+	width: usize,
+	height: usize,
+	cells: Vec<u32>,
+}
+
+impl IndexedGrid {
+	fn new(width: usize, height: usize) -> Self {
+		IndexedGrid {
+			width,
+			height,
+			cells: vec![EMPTY_INDEX; width * height],
+		}
+	}
+
+	fn from_stencil(stencil: &Stencil) -> (Vec<&[u8]>, Self) {
+		let bounds = stencil.bounds();
+		let mut grid = IndexedGrid::new(bounds.w as usize, bounds.h as usize);
+		let mut palette: Vec<&[u8]> = Vec::new();
+		let mut lookup: HashMap<&[u8], u32> = HashMap::new();
+		for (x, y, data) in stencil.iter() {
+			let index = *lookup.entry(data).or_insert_with(|| {
+				palette.push(data);
+				(palette.len() - 1) as u32
+			});
+			let (x, y) = ((x - bounds.x) as usize, (y - bounds.y) as usize);
+			grid.cells[y * grid.width + x] = index;
+		}
+		(palette, grid)
+	}
+
+	fn into_stencil(self, palette: &[&[u8]], channel: Channel, bounds: Rect<i32, i32>) -> Stencil {
+		let mut mask = bitvec![Lsb0, u8; 0; self.cells.len()];
+		let mut data = Vec::with_capacity(self.cells.len() * channel.pixel_stride());
+		for (i, index) in self.cells.iter().enumerate() {
+			if *index != EMPTY_INDEX {
+				mask.set(i, true);
+				data.extend_from_slice(palette[*index as usize]);
+			}
+		}
+		unsafe {
+			Stencil::from_raw_parts(
+				Rect::new(bounds.x, bounds.y, self.width as i32, self.height as i32),
+				mask,
+				channel,
+				data,
+			)
+		}
+	}
+
+	/// Retrieve index at coordinate, clamping to the edges
+	fn get(&self, x: i32, y: i32) -> u32 {
+		let x = x.clamp(0, self.width as i32 - 1) as usize;
+		let y = y.clamp(0, self.height as i32 - 1) as usize;
+		self.cells[y * self.width + x]
+	}
+
+	fn upscale(&self, upscaling: Upscaling, palette: &[&[u8]], channel: Channel) -> Self {
+		let factor = upscaling.factor();
+		let mut out = IndexedGrid::new(self.width * factor, self.height * factor);
+		let mut block = [EMPTY_INDEX; 9];
+		for y in 0..self.height {
+			for x in 0..self.width {
+				let (xi, yi) = (x as i32, y as i32);
+				match upscaling {
+					Upscaling::Scale2x => self.scale2x_block(xi, yi, &mut block),
+					Upscaling::Scale3x => self.scale3x_block(xi, yi, &mut block),
+					Upscaling::Epx => self.epx_block(xi, yi, &mut block),
+					Upscaling::Xbr => self.xbr_block(xi, yi, palette, channel, &mut block),
+				}
+				for j in 0..factor {
+					for i in 0..factor {
+						out.cells[(y * factor + j) * out.width + x * factor + i] =
+							block[j * factor + i];
+					}
+				}
+			}
+		}
+		out
+	}
+
+	fn scale2x_block(&self, x: i32, y: i32, block: &mut [u32; 9]) {
+		let p = self.get(x, y);
+		let a = self.get(x, y - 1);
+		let b = self.get(x + 1, y);
+		let c = self.get(x - 1, y);
+		let d = self.get(x, y + 1);
+		block[0] = if c == a && c != d && a != b { a } else { p };
+		block[1] = if a == b && a != c && b != d { b } else { p };
+		block[2] = if d == c && d != b && c != a { c } else { p };
+		block[3] = if b == d && b != a && d != c { d } else { p };
+	}
+
+	fn epx_block(&self, x: i32, y: i32, block: &mut [u32; 9]) {
+		let p = self.get(x, y);
+		let a = self.get(x, y - 1);
+		let b = self.get(x + 1, y);
+		let c = self.get(x - 1, y);
+		let d = self.get(x, y + 1);
+		let neighbours = [a, b, c, d];
+		let three_alike = neighbours
+			.iter()
+			.any(|n| neighbours.iter().filter(|m| *m == n).count() >= 3);
+		if three_alike {
+			block[..4].copy_from_slice(&[p; 4]);
+		} else {
+			block[0] = if c == a { a } else { p };
+			block[1] = if a == b { b } else { p };
+			block[2] = if d == c { c } else { p };
+			block[3] = if b == d { d } else { p };
+		}
+	}
+
+	fn scale3x_block(&self, x: i32, y: i32, block: &mut [u32; 9]) {
+		let a = self.get(x - 1, y - 1);
+		let b = self.get(x, y - 1);
+		let c = self.get(x + 1, y - 1);
+		let d = self.get(x - 1, y);
+		let e = self.get(x, y);
+		let f = self.get(x + 1, y);
+		let g = self.get(x - 1, y + 1);
+		let h = self.get(x, y + 1);
+		let i = self.get(x + 1, y + 1);
+		*block = [e; 9];
+		if b != h && d != f {
+			block[0] = if d == b { d } else { e };
+			block[1] = if (d == b && e != c) || (b == f && e != a) {
+				b
+			} else {
+				e
+			};
+			block[2] = if b == f { f } else { e };
+			block[3] = if (d == b && e != g) || (d == h && e != a) {
+				d
+			} else {
+				e
+			};
+			block[5] = if (b == f && e != i) || (h == f && e != c) {
+				f
+			} else {
+				e
+			};
+			block[6] = if d == h { d } else { e };
+			block[7] = if (d == h && e != i) || (h == f && e != g) {
+				h
+			} else {
+				e
+			};
+			block[8] = if h == f { f } else { e };
+		}
+	}
+
+	fn xbr_block(&self, x: i32, y: i32, palette: &[&[u8]], channel: Channel, block: &mut [u32; 9]) {
+		let empty = channel.default_pixel();
+		let color = |index: u32| -> &[u8] {
+			match index {
+				EMPTY_INDEX => &empty,
+				index => palette[index as usize],
+			}
+		};
+		let d = |a: u32, b: u32| -> f32 {
+			if a == b {
+				0.
+			} else {
+				color_distance(channel, color(a), color(b))
+			}
+		};
+		// Each corner is handled by mirroring the neighbourhood towards it
+		for (corner, (sx, sy)) in [(-1, -1), (1, -1), (-1, 1), (1, 1)].iter().enumerate() {
+			let at = |u: i32, v: i32| self.get(x + u * sx, y + v * sy);
+			let e = at(0, 0);
+			let f = at(1, 0);
+			let h = at(0, 1);
+			let i = at(1, 1);
+			block[corner] = e;
+			if e != f && e != h {
+				let wd1 =
+					d(e, at(1, -1))
+						+ d(e, at(-1, 1)) + d(i, at(2, 0))
+						+ d(i, at(0, 2)) + 4. * d(h, f);
+				let wd2 =
+					d(h, at(-1, 0))
+						+ d(h, at(1, 2)) + d(f, at(2, 1))
+						+ d(f, at(0, -1)) + 4. * d(e, i);
+				if wd1 < wd2 {
+					block[corner] = if d(e, f) <= d(e, h) { f } else { h };
 				}
 			}
 		}
+	}
+
+	/// Index of a cell of the grid upscaled `level` times with Scale2x
+	///
+	/// Matches `scale2x_block` applied to the whole grid at every level,
+	/// including clamping to the edges of each intermediate grid.
+	fn scale2x_cell(&self, level: u32, x: i32, y: i32) -> u32 {
+		if level == 0 {
+			return self.get(x, y);
+		}
+		let x = x.clamp(0, (self.width << level) as i32 - 1);
+		let y = y.clamp(0, (self.height << level) as i32 - 1);
+		let at = |dx: i32, dy: i32| self.scale2x_cell(level - 1, (x >> 1) + dx, (y >> 1) + dy);
+		let p = at(0, 0);
+		let a = at(0, -1);
+		let b = at(1, 0);
+		let c = at(-1, 0);
+		let d = at(0, 1);
+		match (x & 1, y & 1) {
+			(0, 0) if c == a && c != d && a != b => a,
+			(1, 0) if a == b && a != c && b != d => b,
+			(0, 1) if d == c && d != b && c != a => c,
+			(1, 1) if b == d && b != a && d != c => d,
+			_ => p,
+		}
+	}
+
+	/// Rotate using RotSprite
+	///
+	/// The grid is upscaled 8 times with Scale2x, rotated around its center with
+	/// nearest neighbour and sampled back down at the center of each pixel. Only
+	/// the upscaled cells that are sampled are computed, so memory stays
+	/// proportional to the output.
+	fn rotsprite(&self, angle: f32) -> Self {
+		if self.width == 0 || self.height == 0 {
+			return IndexedGrid::new(self.width, self.height);
+		}
+		let (sin, cos) = angle.sin_cos();
+		let half_w = self.width as f32 / 2.;
+		let half_h = self.height as f32 / 2.;
+		let extent_w = (half_w * cos).abs() + (half_h * sin).abs();
+		let extent_h = (half_w * sin).abs() + (half_h * cos).abs();
+		let mut out = IndexedGrid::new(
+			(extent_w * 2.).round() as usize,
+			(extent_h * 2.).round() as usize,
+		);
+		let out_half_w = out.width as f32 / 2.;
+		let out_half_h = out.height as f32 / 2.;
+		for y in 0..out.height {
+			for x in 0..out.width {
+				let dx = x as f32 + 0.5 - out_half_w;
+				let dy = y as f32 + 0.5 - out_half_h;
+				// Inverse rotation back into source space
+				let sx = dx * cos + dy * sin + half_w;
+				let sy = -dx * sin + dy * cos + half_h;
+				if sx >= 0. && sy >= 0. && sx < self.width as f32 && sy < self.height as f32 {
+					out.cells[y * out.width + x] =
+						self.scale2x_cell(3, (sx * 8.) as i32, (sy * 8.) as i32);
+				}
+			}
+		}
+		out
+	}
+}
 
-		unsafe { Ok(Stencil::from_raw_parts(new_bounds, mask, channel, data)) }
+/// Perceptual distance between two pixels used by xBR
+fn color_distance(channel: Channel, a: &[u8], b: &[u8]) -> f32 {
+	let (bytes, floats) = component_layout(channel);
+	let mut distance = 0f32;
+	let (colors, alpha) = match bytes {
+		2 | 4 => (bytes - 1, true),
+		_ => (bytes, false),
+	};
+	if colors == 3 {
+		let yuv = |p: &[u8]| {
+			let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+			(
+				0.299 * r + 0.587 * g + 0.114 * b,
+				-0.169 * r - 0.331 * g + 0.5 * b,
+				0.5 * r - 0.419 * g - 0.081 * b,
+			)
+		};
+		let (ay, au, av) = yuv(a);
+		let (by, bu, bv) = yuv(b);
+		distance += 48. * (ay - by).abs() + 7. * (au - bu).abs() + 6. * (av - bv).abs();
+	} else if colors == 1 {
+		distance += 48. * (a[0] as f32 - b[0] as f32).abs();
+	}
+	if alpha {
+		distance += 48. * (a[colors] as f32 - b[colors] as f32).abs();
 	}
+	for i in 0..floats {
+		let offset = bytes + i * 4;
+		let mut ra = [0u8; 4];
+		let mut rb = [0u8; 4];
+		ra.copy_from_slice(&a[offset..offset + 4]);
+		rb.copy_from_slice(&b[offset..offset + 4]);
+		distance += 255. * (f32::from_ne_bytes(ra) - f32::from_ne_bytes(rb)).abs();
+	}
+	distance
 }
 
 #[cfg(test)]
@@ -555,4 +1121,348 @@ mod tests {
 		assert_eq!(format!("{:?}", rotated), "Stencil ( ⠛ )");
 		assert_eq!(rotated.data(), &vec![5, 255, 15, 255, 0, 0, 10, 255]);
 	}
+
+	#[test]
+	fn stencil_upscale() {
+		let stencil = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 3),
+			Channel::Luma,
+			vec![1, 0, 0, 0, 1, 0, 0, 0, 1],
+		);
+
+		let scaled = stencil.upscale(Upscaling::Scale2x);
+		assert_eq!(scaled.bounds(), Rect::new(0, 0, 6, 6));
+		assert_eq!(
+			scaled.data(),
+			&vec![
+				1, 1, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1,
+				0, 1, 0, 0, 0, 0, 1, 1
+			]
+		);
+
+		let scaled = stencil.upscale(Upscaling::Epx);
+		assert_eq!(scaled.bounds(), Rect::new(0, 0, 6, 6));
+		assert_eq!(
+			scaled.data(),
+			&vec![
+				1, 1, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1,
+				0, 1, 0, 0, 0, 0, 1, 1
+			]
+		);
+
+		let scaled = stencil.upscale(Upscaling::Xbr);
+		assert_eq!(scaled.bounds(), Rect::new(0, 0, 6, 6));
+		assert_eq!(
+			scaled.data(),
+			&vec![
+				1, 1, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 1,
+				1, 1, 0, 0, 0, 0, 1, 1
+			]
+		);
+
+		let scaled = stencil.upscale(Upscaling::Scale3x);
+		assert_eq!(scaled.bounds(), Rect::new(0, 0, 9, 9));
+		assert_eq!(
+			scaled.data(),
+			&vec![
+				1, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 0, 1, 0, 0, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+				1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 1, 0, 0, 0,
+				0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 1
+			]
+		);
+	}
+
+	#[test]
+	fn stencil_upscale_masked() {
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![0, 0, 5, 255, 5, 255, 0, 0],
+		);
+		let scaled = stencil.upscale(Upscaling::Scale2x);
+		assert_eq!(format!("{:?}", scaled), "Stencil ( ⣔⠝ )");
+		assert_eq!(
+			scaled.data(),
+			&vec![5, 255, 5, 255, 5, 255, 5, 255, 5, 255, 5, 255, 5, 255, 5, 255]
+		);
+	}
+
+	#[test]
+	fn stencil_rotsprite() {
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![0, 0, 5, 255, 10, 255, 15, 255],
+		);
+
+		let rotated = stencil.rotsprite(0.);
+		assert_eq!(format!("{:?}", rotated), "Stencil ( ⠚ )");
+		assert_eq!(rotated.data(), &vec![5, 255, 10, 255, 15, 255]);
+
+		let rotated = stencil.rotsprite(90. * (std::f32::consts::PI / 180.));
+		assert_eq!(format!("{:?}", rotated), "Stencil ( ⠓ )");
+		assert_eq!(rotated.data(), &vec![10, 255, 15, 255, 5, 255]);
+
+		let rotated = stencil.rotsprite(45. * (std::f32::consts::PI / 180.));
+		assert_eq!(format!("{:?}", rotated), "Stencil ( ⠲⠂ )");
+		assert_eq!(rotated.data(), &vec![10, 255, 15, 255, 5, 255, 15, 255]);
+	}
+
+	#[test]
+	fn rotsprite_lazy_upscale() {
+		// Irregular sprite with enough structure for Scale2x to kick in
+		let grid = IndexedGrid {
+			width: 5,
+			height: 4,
+			cells: vec![
+				0,
+				0,
+				1,
+				EMPTY_INDEX,
+				2,
+				0,
+				1,
+				1,
+				2,
+				2,
+				EMPTY_INDEX,
+				1,
+				0,
+				0,
+				2,
+				3,
+				3,
+				0,
+				1,
+				1,
+			],
+		};
+		let mut large = IndexedGrid {
+			width: grid.width,
+			height: grid.height,
+			cells: grid.cells.clone(),
+		};
+		for _ in 0..3 {
+			large = large.upscale(Upscaling::Scale2x, &[], Channel::Luma);
+		}
+		for y in 0..large.height {
+			for x in 0..large.width {
+				assert_eq!(
+					grid.scale2x_cell(3, x as i32, y as i32),
+					large.cells[y * large.width + x]
+				);
+			}
+		}
+	}
+
+	#[test]
+	fn canvas_upscale() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![0, 0, 5, 255, 5, 255, 0, 0],
+		));
+		let scaled = canvas.upscale(Upscaling::Scale2x);
+		assert_eq!(scaled.bounds(), Rect::new(0, 0, 4, 4));
+		let pixels: Vec<_> = scaled.iter().flatten().copied().collect();
+		assert_eq!(
+			pixels,
+			vec![
+				0, 0, 0, 0, 5, 255, 5, 255, 0, 0, 5, 255, 0, 0, 5, 255, 5, 255, 0, 0, 5, 255, 0, 0,
+				5, 255, 5, 255, 0, 0, 0, 0
+			]
+		);
+	}
+
+	#[test]
+	fn stencil_transform_repeat() {
+		let stencil = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 1),
+			Channel::Lumaa,
+			vec![5, 255, 10, 255, 15, 255],
+		);
+
+		let translated =
+			stencil.transform(Sampling::Nearest, &Mat3::translation_2d(Vec2::new(1., 0.)));
+		assert!(translated.is_ok());
+		let translated = translated.unwrap();
+		assert_eq!(format!("{:?}", translated), "Stencil ( ⠈⠁ )");
+		assert_eq!(translated.data(), &vec![5, 255, 10, 255]);
+
+		let translated = stencil.transform_with_addressing(
+			Sampling::Nearest,
+			Addressing::Repeat,
+			&Mat3::translation_2d(Vec2::new(1., 0.)),
+		);
+		assert!(translated.is_ok());
+		let translated = translated.unwrap();
+		assert_eq!(format!("{:?}", translated), "Stencil ( ⠉⠁ )");
+		assert_eq!(translated.data(), &vec![15, 255, 5, 255, 10, 255]);
+	}
+
+	#[test]
+	fn transform_away_from_origin() {
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(10, -4, 2, 2),
+			Channel::Lumaa,
+			vec![0, 0, 5, 255, 10, 255, 15, 255],
+		);
+		let same = stencil
+			.transform(Sampling::Nearest, &Mat3::identity())
+			.unwrap();
+		assert_eq!(same.bounds(), Rect::new(10, -4, 2, 2));
+		assert_eq!(format!("{:?}", same), "Stencil ( ⠚ )");
+		assert_eq!(same.data(), &vec![5, 255, 10, 255, 15, 255]);
+
+		let flipped = stencil
+			.transform(Sampling::Nearest, &Mat3::scaling_3d(Vec3::new(-1., 1., 1.)))
+			.unwrap();
+		assert_eq!(flipped.bounds(), Rect::new(10, -4, 2, 2));
+		assert_eq!(format!("{:?}", flipped), "Stencil ( ⠓ )");
+		assert_eq!(flipped.data(), &vec![5, 255, 15, 255, 10, 255]);
+
+		let canvas = Canvas::from_stencil(stencil)
+			.transform(Sampling::Nearest, &Mat3::identity())
+			.unwrap();
+		assert_eq!(canvas.bounds(), Rect::new(10, -4, 2, 2));
+		assert_eq!(canvas[(11, -4)], [5, 255]);
+		assert_eq!(canvas[(10, -3)], [10, 255]);
+		assert_eq!(canvas[(11, -3)], [15, 255]);
+	}
+
+	#[test]
+	fn bounds_transformed() {
+		let bounds = Rect::new(10, 10, 4, 2);
+		assert_eq!(
+			transformed_bounds(bounds, Vec2::new(0., 0.), &Mat3::identity()),
+			bounds
+		);
+		assert_eq!(
+			transformed_bounds(
+				bounds,
+				Vec2::new(10., 10.),
+				&Mat3::rotation_z(90. * (std::f32::consts::PI / 180.))
+			),
+			Rect::new(8, 10, 2, 4)
+		);
+		assert_eq!(
+			transformed_bounds(
+				bounds,
+				Vec2::new(12., 11.),
+				&Mat3::rotation_z(90. * (std::f32::consts::PI / 180.))
+			),
+			Rect::new(11, 9, 2, 4)
+		);
+		assert_eq!(
+			transformed_bounds(
+				bounds,
+				Vec2::new(12., 11.),
+				&Mat3::scaling_3d(Vec3::new(2., 2., 1.))
+			),
+			Rect::new(8, 9, 8, 4)
+		);
+		assert_eq!(
+			transformed_bounds(
+				bounds,
+				Vec2::new(0., 0.),
+				&Mat3::translation_2d(Vec2::new(-3., 5.))
+			),
+			Rect::new(7, 15, 4, 2)
+		);
+		// Perspective, the far edge shrinks towards the pivot
+		let mut perspective = Mat3::identity();
+		perspective.cols[1].z = 0.5;
+		assert_eq!(
+			transformed_bounds(Rect::new(-2, 0, 4, 2), Vec2::new(0., 0.), &perspective),
+			Rect::new(-2, 0, 4, 1)
+		);
+		perspective.cols[1].z = -1.;
+		assert_eq!(
+			transformed_bounds(Rect::new(-2, 0, 4, 2), Vec2::new(0., 0.), &perspective),
+			Rect::new(0, 0, 0, 0)
+		);
+	}
+
+	#[test]
+	fn stencil_transform_around() {
+		let stencil = Stencil::from_buffer_mask_alpha(
+			Rect::new(10, 10, 2, 2),
+			Channel::Lumaa,
+			vec![0, 0, 5, 255, 10, 255, 15, 255],
+		);
+
+		let same = stencil
+			.transform_around(
+				Sampling::Nearest,
+				Addressing::Border,
+				Vec2::new(0., 0.),
+				&Mat3::identity(),
+			)
+			.unwrap();
+		assert_eq!(same.bounds(), Rect::new(10, 10, 2, 2));
+		assert_eq!(format!("{:?}", same), "Stencil ( ⠚ )");
+		assert_eq!(same.data(), &vec![5, 255, 10, 255, 15, 255]);
+
+		let rotated = stencil
+			.transform_around(
+				Sampling::Nearest,
+				Addressing::Border,
+				Vec2::new(10., 10.),
+				&Mat3::rotation_z(90. * (std::f32::consts::PI / 180.)),
+			)
+			.unwrap();
+		assert_eq!(rotated.bounds(), Rect::new(8, 10, 2, 2));
+		assert_eq!(format!("{:?}", rotated), "Stencil ( ⠓ )");
+		assert_eq!(rotated.data(), &vec![10, 255, 15, 255, 5, 255]);
+
+		let scaled = stencil
+			.transform_around(
+				Sampling::Nearest,
+				Addressing::Border,
+				Vec2::new(11., 11.),
+				&Mat3::scaling_3d(Vec3::new(2., 2., 1.)),
+			)
+			.unwrap();
+		assert_eq!(scaled.bounds(), Rect::new(9, 9, 4, 4));
+		assert_eq!(format!("{:?}", scaled), "Stencil ( ⣤⣿ )");
+		assert_eq!(
+			scaled.data(),
+			&vec![
+				5, 255, 5, 255, 5, 255, 5, 255, 10, 255, 10, 255, 15, 255, 15, 255, 10, 255, 10,
+				255, 15, 255, 15, 255
+			]
+		);
+	}
+
+	#[test]
+	fn canvas_transform_around() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(4, 4, 2, 2),
+			Channel::Lumaa,
+			vec![0, 0, 5, 255, 10, 255, 15, 255],
+		));
+		let rotated = canvas
+			.transform_around(
+				Sampling::Nearest,
+				Addressing::Border,
+				Vec2::new(5., 5.),
+				&Mat3::rotation_z(180. * (std::f32::consts::PI / 180.)),
+			)
+			.unwrap();
+		assert_eq!(rotated.bounds(), Rect::new(4, 4, 2, 2));
+		let pixels: Vec<_> = rotated.iter().flatten().copied().collect();
+		assert_eq!(pixels, vec![15, 255, 10, 255, 5, 255, 0, 0]);
+
+		let translated = canvas
+			.transform_around(
+				Sampling::Nearest,
+				Addressing::Border,
+				Vec2::new(0., 0.),
+				&Mat3::translation_2d(Vec2::new(-4., 1.)),
+			)
+			.unwrap();
+		assert_eq!(translated.bounds(), Rect::new(0, 5, 2, 2));
+		let pixels: Vec<_> = translated.iter().flatten().copied().collect();
+		assert_eq!(pixels, vec![0, 0, 5, 255, 10, 255, 15, 255]);
+	}
 }
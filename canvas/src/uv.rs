@@ -0,0 +1,149 @@
+use crate::{
+	check_channel, components, read_color, Addressing, Canvas, Samplable, Sampling, Stencil,
+};
+use color::{Channel, ChannelError, Color};
+use std::sync::Arc;
+
+/// Texture lookups through `Uv` coordinates
+///
+/// Coordinates span the texture bounds from 0 to 1, left to right and top to
+/// bottom, and land on pixel centers as in `Transformable`.
+pub trait UvMappable {
+	type Output;
+
+	/// Sample texture at every coordinate of this `Uv` image into `Rgba`
+	fn remap(
+		&self,
+		texture: &Canvas,
+		sampling: Sampling,
+		addressing: Addressing,
+	) -> Result<Self::Output, ChannelError>;
+}
+
+impl UvMappable for Stencil {
+	type Output = Stencil;
+
+	fn remap(
+		&self,
+		texture: &Canvas,
+		sampling: Sampling,
+		addressing: Addressing,
+	) -> Result<Self::Output, ChannelError> {
+		if self.channel() != Channel::Uv {
+			return Err(ChannelError::Mismatch(self.channel(), Channel::Uv));
+		}
+		let channel = texture.channel();
+		check_channel(channel)?;
+		let bounds = texture.bounds();
+		let empty = channel.default_pixel();
+		Ok(Stencil::from_fn(
+			self.bounds(),
+			Channel::Rgba,
+			|x, y, out| {
+				let pixel = match self.try_get(x, y) {
+					Some(pixel) => pixel,
+					None => return false,
+				};
+				let uv = components(Channel::Uv, pixel);
+				let position = (
+					bounds.x as f32 + uv[0] * bounds.w as f32 - 0.5,
+					bounds.y as f32 + uv[1] * bounds.h as f32 - 0.5,
+				);
+				// Lookups outside of the texture stay transparent
+				let mut texel = empty.clone();
+				if texture
+					.sample2d(position, sampling, addressing, &mut texel)
+					.is_ok()
+				{
+					out.copy_from_slice(read_color(channel, &texel).to_slice());
+				}
+				true
+			},
+		))
+	}
+}
+
+impl UvMappable for Canvas {
+	type Output = Canvas;
+
+	fn remap(
+		&self,
+		texture: &Canvas,
+		sampling: Sampling,
+		addressing: Addressing,
+	) -> Result<Self::Output, ChannelError> {
+		if self.channel() != Channel::Uv {
+			return Err(ChannelError::Mismatch(self.channel(), Channel::Uv));
+		}
+		check_channel(texture.channel())?;
+		let tiles = self
+			.tiles()
+			.values()
+			.map(|tile| tile.remap(texture, sampling, addressing).map(Arc::new))
+			.collect::<Result<Vec<_>, _>>()?;
+		Ok(unsafe { Canvas::from_raw_parts(Channel::Rgba, tiles) })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use vek::geom::repr_c::Rect;
+
+	fn uv(coords: &[(f32, f32)]) -> Vec<u8> {
+		coords
+			.iter()
+			.flat_map(|(u, v)| {
+				let mut bytes = u.to_ne_bytes().to_vec();
+				bytes.extend_from_slice(&v.to_ne_bytes());
+				bytes
+			})
+			.collect()
+	}
+
+	#[test]
+	fn remap() {
+		// 2x2 texture of distinct grays
+		let texture = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(10, 10, 2, 2),
+			Channel::Luma,
+			vec![10, 20, 30, 40],
+		));
+		let a = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 1),
+			Channel::Uv,
+			uv(&[(0.75, 0.25), (0.25, 0.75), (2., 2.)]),
+		);
+		let b = a
+			.remap(&texture, Sampling::Nearest, Addressing::Border)
+			.unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		assert_eq!(b.try_get(0, 0), Some(&[20, 20, 20, 255][..]));
+		assert_eq!(b.try_get(1, 0), Some(&[30, 30, 30, 255][..]));
+		assert_eq!(b.try_get(2, 0), Some(&[0, 0, 0, 0][..]));
+
+		let b = Canvas::from_stencil(a.clone())
+			.remap(&texture, Sampling::Nearest, Addressing::Repeat)
+			.unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		assert_eq!(b[(1, 0)], [30, 30, 30, 255]);
+		assert_eq!(b[(2, 0)], [10, 10, 10, 255]);
+
+		assert!(texture
+			.remap(&texture, Sampling::Nearest, Addressing::Border)
+			.is_err());
+	}
+
+	#[test]
+	fn empty_canvas() {
+		let texture = Canvas::new(Channel::Rgb);
+		let remap = |canvas: Canvas, texture: &Canvas| {
+			canvas.remap(texture, Sampling::Nearest, Addressing::Clamp)
+		};
+		assert!(remap(Canvas::new(Channel::Rgba), &texture).is_err());
+		assert!(remap(Canvas::new(Channel::Uv), &Canvas::new(Channel::Normal)).is_err());
+		let b = remap(Canvas::new(Channel::Uv), &texture).unwrap();
+		assert_eq!(b.channel(), Channel::Rgba);
+		assert!(b.tiles().is_empty());
+	}
+}
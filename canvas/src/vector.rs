@@ -0,0 +1,409 @@
+use crate::{check_channel, read_color, Stencil};
+use color::{ChannelError, Rgba};
+use std::{
+	collections::{BTreeMap, HashMap},
+	fmt::Write,
+};
+use vek::vec::repr_c::vec2::Vec2;
+
+/// Closed outline following pixel edges
+///
+/// Exteriors run clockwise and holes counter-clockwise, with y pointing down.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+	pub exterior: Vec<Vec2<i32>>,
+	pub holes: Vec<Vec<Vec2<i32>>>,
+}
+
+/// How pixels are written to SVG
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SvgMode {
+	/// One path per color, traced around its pixels
+	Paths,
+	/// One rect per run of same colored pixels in a row
+	Runs,
+}
+
+impl Stencil {
+	/// Trace visible pixels into polygons, keeping diagonal neighbours apart
+	///
+	/// Masked-in pixels with a zero alpha count as empty, as in `to_svg`.
+	pub fn trace(&self) -> Vec<Polygon> {
+		let bounds = self.bounds();
+		let filled = |x: i32, y: i32| match self.try_get(x, y) {
+			Some(pixel) => self.is_visible(pixel),
+			None => false,
+		};
+		let mut edges = Edges::new();
+		for y in bounds.y..bounds.y + bounds.h {
+			for x in bounds.x..bounds.x + bounds.w {
+				if filled(x, y) {
+					add_edges(&mut edges, x, y, filled);
+				}
+			}
+		}
+		chain_edges(edges)
+	}
+
+	/// Export visible pixels to an SVG document
+	pub fn to_svg(&self, mode: SvgMode) -> Result<String, ChannelError> {
+		let channel = self.channel();
+		check_channel(channel)?;
+		let bounds = self.bounds();
+		let mut svg = String::new();
+		let _ = write!(
+			svg,
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{}\" height=\"{}\" shape-rendering=\"crispEdges\">",
+			bounds.x, bounds.y, bounds.w, bounds.h, bounds.w, bounds.h
+		);
+		match mode {
+			SvgMode::Runs => {
+				let stride = channel.pixel_stride();
+				for (y, x0, _, data) in self.spans() {
+					let mut run: Option<(i32, Rgba)> = None;
+					for (i, pixel) in data.chunks(stride).enumerate() {
+						let color = read_color(channel, pixel);
+						match run {
+							Some((_, current)) if current == color => {}
+							_ => {
+								if let Some((start, current)) = run {
+									svg_rect(&mut svg, start, y, x0 + i as i32 - start, current);
+								}
+								run = Some((x0 + i as i32, color));
+							}
+						}
+					}
+					if let Some((start, current)) = run {
+						let end = x0 + (data.len() / stride) as i32;
+						svg_rect(&mut svg, start, y, end - start, current);
+					}
+				}
+			}
+			SvgMode::Paths => {
+				// Color of every visible pixel, indexing colors in order of first appearance
+				let mut colors: Vec<Rgba> = vec![];
+				let mut lookup: HashMap<(u8, u8, u8, u8), usize> = HashMap::new();
+				let mut ids: Vec<Option<usize>> = vec![None; (bounds.w * bounds.h) as usize];
+				for (x, y, pixel) in self.iter() {
+					let color = read_color(channel, pixel);
+					if color.alpha > 0 {
+						let key = (
+							color.color.red,
+							color.color.green,
+							color.color.blue,
+							color.alpha,
+						);
+						let id = *lookup.entry(key).or_insert_with(|| {
+							colors.push(color);
+							colors.len() - 1
+						});
+						ids[((y - bounds.y) * bounds.w + x - bounds.x) as usize] = Some(id);
+					}
+				}
+				let id_at = |x: i32, y: i32| {
+					if x < bounds.x
+						|| y < bounds.y || x >= bounds.x + bounds.w
+						|| y >= bounds.y + bounds.h
+					{
+						None
+					} else {
+						ids[((y - bounds.y) * bounds.w + x - bounds.x) as usize]
+					}
+				};
+
+				// Edges of every color, collected in a single pass
+				let mut edges: Vec<Edges> = vec![Edges::new(); colors.len()];
+				for y in bounds.y..bounds.y + bounds.h {
+					for x in bounds.x..bounds.x + bounds.w {
+						if let Some(id) = id_at(x, y) {
+							add_edges(&mut edges[id], x, y, |x, y| id_at(x, y) == Some(id));
+						}
+					}
+				}
+
+				for (color, edges) in colors.into_iter().zip(edges) {
+					let mut path = String::new();
+					for polygon in chain_edges(edges).iter() {
+						for ring in std::iter::once(&polygon.exterior).chain(polygon.holes.iter()) {
+							for (i, point) in ring.iter().enumerate() {
+								let command = if i == 0 { 'M' } else { 'L' };
+								let _ = write!(path, "{}{} {}", command, point.x, point.y);
+							}
+							path.push('Z');
+						}
+					}
+					let _ = write!(svg, "<path d=\"{}\" fill=\"{}\"", path, css_color(color));
+					if color.alpha < 255 {
+						let _ = write!(svg, " fill-opacity=\"{:.3}\"", color.alpha as f32 / 255.);
+					}
+					svg.push_str("/>");
+				}
+			}
+		}
+		svg.push_str("</svg>");
+		Ok(svg)
+	}
+}
+
+fn svg_rect(svg: &mut String, x: i32, y: i32, w: i32, color: Rgba) {
+	if color.alpha == 0 {
+		return;
+	}
+	let _ = write!(
+		svg,
+		"<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"1\" fill=\"{}\"",
+		x,
+		y,
+		w,
+		css_color(color)
+	);
+	if color.alpha < 255 {
+		let _ = write!(svg, " fill-opacity=\"{:.3}\"", color.alpha as f32 / 255.);
+	}
+	svg.push_str("/>");
+}
+
+fn css_color(color: Rgba) -> String {
+	format!(
+		"#{:02x}{:02x}{:02x}",
+		color.color.red, color.color.green, color.color.blue
+	)
+}
+
+/// Outgoing edges by start vertex, keyed (y, x) to start loops at the top-left
+type Edges = BTreeMap<(i32, i32), Vec<(i32, i32)>>;
+
+/// Add the edges of a filled pixel that face pixels not filled alike
+///
+/// Edges are oriented so the filled side is on the right.
+fn add_edges<F: Fn(i32, i32) -> bool>(edges: &mut Edges, x: i32, y: i32, filled: F) {
+	let mut add = |(x0, y0): (i32, i32), (x1, y1): (i32, i32)| {
+		edges.entry((y0, x0)).or_default().push((x1, y1));
+	};
+	if !filled(x, y - 1) {
+		add((x, y), (x + 1, y));
+	}
+	if !filled(x + 1, y) {
+		add((x + 1, y), (x + 1, y + 1));
+	}
+	if !filled(x, y + 1) {
+		add((x + 1, y + 1), (x, y + 1));
+	}
+	if !filled(x - 1, y) {
+		add((x, y + 1), (x, y));
+	}
+}
+
+/// Chain pixel edges into polygons, turning right where two loops touch at
+/// a corner
+fn chain_edges(mut edges: Edges) -> Vec<Polygon> {
+	let mut exteriors: Vec<(Vec<Vec2<i32>>, i64)> = vec![];
+	let mut holes: Vec<Vec<Vec2<i32>>> = vec![];
+	while let Some((&(y, x), _)) = edges.iter().next() {
+		let start = (x, y);
+		let mut ring = vec![];
+		let mut current = start;
+		let mut direction = (0, 0);
+		loop {
+			let key = (current.1, current.0);
+			let outgoing = edges.get_mut(&key).unwrap();
+			let index = if outgoing.len() > 1 {
+				// Prefer the right turn, toward the filled side
+				let right = (-direction.1, direction.0);
+				outgoing
+					.iter()
+					.position(|next| (next.0 - current.0, next.1 - current.1) == right)
+					.unwrap_or(0)
+			} else {
+				0
+			};
+			let next = outgoing.remove(index);
+			if outgoing.is_empty() {
+				edges.remove(&key);
+			}
+			let next_direction = (next.0 - current.0, next.1 - current.1);
+			if next_direction != direction {
+				ring.push(Vec2::new(current.0, current.1));
+			}
+			direction = next_direction;
+			current = next;
+			if current == start {
+				break;
+			}
+		}
+		// The start vertex is a corner unless the loop closes straight through it
+		if ring.len() > 1 {
+			let first = ring[0];
+			let second = ring[1];
+			if (second.x - first.x).signum() == direction.0
+				&& (second.y - first.y).signum() == direction.1
+			{
+				ring.remove(0);
+			}
+		}
+
+		let area = ring_area(&ring);
+		if area > 0 {
+			exteriors.push((ring, area));
+		} else {
+			holes.push(ring);
+		}
+	}
+
+	let mut polygons: Vec<Polygon> = exteriors
+		.iter()
+		.map(|(exterior, _)| Polygon {
+			exterior: exterior.clone(),
+			holes: vec![],
+		})
+		.collect();
+	for hole in holes {
+		// Center of the empty pixel left of the first edge lies inside the hole
+		let (a, b) = (hole[0], hole[1]);
+		let d = ((b.x - a.x).signum() as f64, (b.y - a.y).signum() as f64);
+		let point = (
+			a.x as f64 + d.0 * 0.5 + d.1 * 0.5,
+			a.y as f64 + d.1 * 0.5 - d.0 * 0.5,
+		);
+		let parent = exteriors
+			.iter()
+			.enumerate()
+			.filter(|(_, (exterior, _))| contains(exterior, point))
+			.min_by_key(|(_, (_, area))| *area)
+			.map(|(i, _)| i);
+		if let Some(parent) = parent {
+			polygons[parent].holes.push(hole);
+		}
+	}
+	polygons
+}
+
+/// Twice the signed area of a ring, positive when clockwise with y down
+fn ring_area(ring: &[Vec2<i32>]) -> i64 {
+	ring.iter()
+		.zip(ring.iter().cycle().skip(1))
+		.map(|(a, b)| a.x as i64 * b.y as i64 - b.x as i64 * a.y as i64)
+		.sum()
+}
+
+/// Even-odd point in ring test
+fn contains(ring: &[Vec2<i32>], point: (f64, f64)) -> bool {
+	let mut inside = false;
+	for (a, b) in ring.iter().zip(ring.iter().cycle().skip(1)) {
+		let (ax, ay, bx, by) = (a.x as f64, a.y as f64, b.x as f64, b.y as f64);
+		if (ay > point.1) != (by > point.1) && point.0 < ax + (point.1 - ay) / (by - ay) * (bx - ax)
+		{
+			inside = !inside;
+		}
+	}
+	inside
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use color::Channel;
+	use vek::geom::repr_c::Rect;
+
+	fn points(points: &[(i32, i32)]) -> Vec<Vec2<i32>> {
+		points.iter().map(|(x, y)| Vec2::new(*x, *y)).collect()
+	}
+
+	#[test]
+	fn trace_square() {
+		let a = Stencil::from_buffer(Rect::new(1, 1, 2, 2), Channel::Luma, vec![1; 4]);
+		assert_eq!(
+			a.trace(),
+			vec![Polygon {
+				exterior: points(&[(1, 1), (3, 1), (3, 3), (1, 3)]),
+				holes: vec![],
+			}]
+		);
+	}
+
+	#[test]
+	fn trace_holes_and_diagonals() {
+		// A ring around a hole, and two pixels touching at a corner
+		let mut buffer = [1u8; 9];
+		buffer[4] = 0;
+		let ring = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 3, 3),
+			Channel::Lumaa,
+			buffer.iter().flat_map(|v| vec![*v, *v * 255]).collect(),
+		);
+		assert_eq!(
+			ring.trace(),
+			vec![Polygon {
+				exterior: points(&[(0, 0), (3, 0), (3, 3), (0, 3)]),
+				holes: vec![points(&[(1, 1), (1, 2), (2, 2), (2, 1)])],
+			}]
+		);
+
+		let diagonal = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 2, 2),
+			Channel::Lumaa,
+			vec![1, 255, 0, 0, 0, 0, 1, 255],
+		);
+		let polygons = diagonal.trace();
+		assert_eq!(polygons.len(), 2);
+		assert_eq!(
+			polygons[0].exterior,
+			points(&[(0, 0), (1, 0), (1, 1), (0, 1)])
+		);
+		assert_eq!(
+			polygons[1].exterior,
+			points(&[(1, 1), (2, 1), (2, 2), (1, 2)])
+		);
+	}
+
+	#[test]
+	fn trace_zero_alpha() {
+		// Masked-in but transparent pixels are left out, as in the SVG export
+		let a = Stencil::from_buffer(
+			Rect::new(0, 0, 3, 1),
+			Channel::Lumaa,
+			vec![1, 255, 1, 0, 1, 255],
+		);
+		let polygons = a.trace();
+		assert_eq!(polygons.len(), 2);
+		assert_eq!(
+			polygons[1].exterior,
+			points(&[(2, 0), (3, 0), (3, 1), (2, 1)])
+		);
+	}
+
+	#[test]
+	fn svg() {
+		let a = Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 0, 3, 1),
+			Channel::Rgba,
+			vec![255, 0, 0, 255, 255, 0, 0, 255, 0, 0, 255, 128],
+		);
+		assert_eq!(
+			a.to_svg(SvgMode::Runs).unwrap(),
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 3 1\" width=\"3\" height=\"1\" shape-rendering=\"crispEdges\">\
+			<rect x=\"0\" y=\"0\" width=\"2\" height=\"1\" fill=\"#ff0000\"/>\
+			<rect x=\"2\" y=\"0\" width=\"1\" height=\"1\" fill=\"#0000ff\" fill-opacity=\"0.502\"/>\
+			</svg>"
+		);
+		assert_eq!(
+			a.to_svg(SvgMode::Paths).unwrap(),
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 3 1\" width=\"3\" height=\"1\" shape-rendering=\"crispEdges\">\
+			<path d=\"M0 0L2 0L2 1L0 1Z\" fill=\"#ff0000\"/>\
+			<path d=\"M2 0L3 0L3 1L2 1Z\" fill=\"#0000ff\" fill-opacity=\"0.502\"/>\
+			</svg>"
+		);
+		// Same colors apart share a path, neighbouring colors split at their edge
+		let a = Stencil::from_buffer(Rect::new(0, 0, 3, 2), Channel::Luma, vec![0, 9, 0, 9, 9, 9]);
+		assert_eq!(
+			a.to_svg(SvgMode::Paths).unwrap(),
+			"<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 3 2\" width=\"3\" height=\"2\" shape-rendering=\"crispEdges\">\
+			<path d=\"M0 0L1 0L1 1L0 1ZM2 0L3 0L3 1L2 1Z\" fill=\"#000000\"/>\
+			<path d=\"M1 0L2 0L2 1L3 1L3 2L0 2L0 1L1 1Z\" fill=\"#090909\"/>\
+			</svg>"
+		);
+
+		let a = Stencil::new(Rect::new(0, 0, 1, 1), Channel::Uv);
+		assert!(a.to_svg(SvgMode::Runs).is_err());
+	}
+}
@@ -1,6 +1,7 @@
 use crate::{HasBounds, HasColors, Node};
-use color::Rgba;
-use std::sync::Arc;
+use canvas::{ColorMap, ColorRamp};
+use color::{Channel, Color, Lumaa, Rgba};
+use std::{ops::Range, sync::Arc};
 use uuid::Uuid;
 use vek::{geom::repr_c::Rect, vec::repr_c::vec2::Vec2};
 
@@ -12,6 +13,23 @@ pub struct Palette {
 	colors: Vec<Rgba>,
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PaletteError {
+	LengthMismatch(usize, usize),
+}
+
+impl std::error::Error for PaletteError {}
+
+impl std::fmt::Display for PaletteError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			PaletteError::LengthMismatch(a, b) => {
+				write!(f, "Palette length mismatch {} != {}", a, b)
+			}
+		}
+	}
+}
+
 impl Palette {
 	pub unsafe fn construct(
 		id: Uuid,
@@ -33,6 +51,42 @@ impl Palette {
 	) -> Self {
 		unsafe { Palette::construct(Uuid::new_v4(), name.into(), position.into(), colors.into()) }
 	}
+
+	/// Map each color to the color at the same index of another palette of the
+	/// same length, as bytes of `channel`
+	pub fn color_map(&self, to: &Palette, channel: Channel) -> Result<ColorMap, PaletteError> {
+		if self.colors.len() != to.colors.len() {
+			return Err(PaletteError::LengthMismatch(
+				self.colors.len(),
+				to.colors.len(),
+			));
+		}
+		let mut map = ColorMap::default();
+		for (from, to) in self.colors.iter().zip(to.colors.iter()) {
+			if let (Some(from), Some(to)) = (color_bytes(from, channel), color_bytes(to, channel)) {
+				map.insert(&from, &to);
+			}
+		}
+		Ok(map)
+	}
+
+	/// Ramp spreading a range of colors evenly, if the range is not empty
+	pub fn color_ramp(&self, range: Range<usize>) -> Option<ColorRamp> {
+		match self.colors.get(range) {
+			Some(colors) if !colors.is_empty() => Some(ColorRamp::from_colors(colors)),
+			_ => None,
+		}
+	}
+}
+
+fn color_bytes(color: &Rgba, channel: Channel) -> Option<Vec<u8>> {
+	match channel {
+		Channel::Rgba | Channel::RgbaNormal => Some(color.to_slice().to_vec()),
+		Channel::Rgb | Channel::RgbNormal => Some(color.to_slice()[..3].to_vec()),
+		Channel::Lumaa | Channel::LumaaNormal => Some(Lumaa::from(*color).to_slice().to_vec()),
+		Channel::Luma | Channel::LumaNormal => Some(Lumaa::from(*color).to_slice()[..1].to_vec()),
+		Channel::Uv | Channel::Normal => None,
+	}
 }
 
 impl Default for Palette {
@@ -84,4 +138,55 @@ mod tests {
 		assert_eq!(note.name(), "Palette");
 		assert_eq!(note.bounds().into_aabr().min, Vec2::new(0, 0));
 	}
+
+	#[test]
+	fn color_map() {
+		use color::Rgb;
+		let a = Palette::new(
+			"A",
+			(0, 0),
+			vec![
+				Rgba::new(Rgb::new(255, 0, 0), 255),
+				Rgba::new(Rgb::new(0, 255, 0), 128),
+			],
+		);
+		let b = Palette::new(
+			"B",
+			(0, 0),
+			vec![
+				Rgba::new(Rgb::new(0, 0, 255), 255),
+				Rgba::new(Rgb::new(0, 0, 0), 255),
+			],
+		);
+		let map = a.color_map(&b, Channel::Rgba).unwrap();
+		assert_eq!(map.entries().len(), 2);
+		assert_eq!(map.lookup(&[255, 0, 0, 255]), Some(&[0, 0, 255, 255][..]));
+		assert_eq!(map.lookup(&[0, 255, 0, 128]), Some(&[0, 0, 0, 255][..]));
+		let map = a.color_map(&b, Channel::Rgb).unwrap();
+		assert_eq!(map.lookup(&[255, 0, 0]), Some(&[0, 0, 255][..]));
+		let map = a.color_map(&b, Channel::Luma).unwrap();
+		assert!(map.entries().keys().all(|from| from.len() == 1));
+		let map = a.color_map(&b, Channel::Normal).unwrap();
+		assert!(map.entries().is_empty());
+
+		let c = Palette::new("C", (0, 0), vec![Rgba::new(Rgb::new(0, 0, 255), 255)]);
+		assert_eq!(
+			a.color_map(&c, Channel::Rgba).unwrap_err(),
+			PaletteError::LengthMismatch(2, 1)
+		);
+	}
+
+	#[test]
+	fn color_ramp() {
+		use color::Rgb;
+		let colors: Vec<Rgba> = (0..4u8)
+			.map(|i| Rgba::new(Rgb::new(i * 10, 0, 0), 255))
+			.collect();
+		let palette = Palette::new("A", (0, 0), colors.clone());
+		let ramp = palette.color_ramp(1..3).unwrap();
+		assert_eq!(ramp.sample(0.), colors[1]);
+		assert_eq!(ramp.sample(1.), colors[2]);
+		assert!(palette.color_ramp(2..2).is_none());
+		assert!(palette.color_ramp(3..9).is_none());
+	}
 }
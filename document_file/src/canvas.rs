@@ -2,14 +2,15 @@ use crate::{Parse, Write};
 use async_std::io;
 use async_trait::async_trait;
 use bitvec::{order::Lsb0, vec::BitVec};
-use canvas::{Canvas, Stencil};
-use color::Channel;
+use canvas::{Canvas, Stencil, TileIndex};
+use color::{Channel, ChannelError};
 use nom::{
+	combinator::opt,
 	multi::many_m_n,
-	number::complete::{le_u32, le_u8},
+	number::complete::{le_i32, le_u32, le_u8},
 	IResult,
 };
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
 use vek::geom::repr_c::Rect;
 
 impl Parse for Stencil {
@@ -19,7 +20,8 @@ impl Parse for Stencil {
 		// Parse mask
 		let len = (((bounds.w * bounds.h) + 8 - 1) / 8) as usize;
 		let (bytes, buffer) = many_m_n(len, len, le_u8)(bytes)?;
-		let mask: BitVec<Lsb0, u8> = BitVec::from_vec(buffer);
+		let mut mask: BitVec<Lsb0, u8> = BitVec::from_vec(buffer);
+		mask.truncate((bounds.w * bounds.h) as usize);
 		// Parse channel
 		let (bytes, channel) = Channel::parse(bytes)?;
 		// Parse data
@@ -60,10 +62,43 @@ impl Parse for Canvas {
 		let (bytes, len) = le_u32(bytes)?;
 		let (bytes, stencils) = many_m_n(len as usize, len as usize, Stencil::parse)(bytes)?;
 		let stencils = stencils.into_iter().map(|s| Arc::new(s)).collect();
-		Ok((bytes, unsafe { Canvas::from_raw_parts(channel, stencils) }))
+		let canvas = unsafe { Canvas::from_raw_parts(channel, stencils) };
+		// Parse working area
+		let (bytes, area) = parse_area(bytes)?;
+		Ok((bytes, canvas.with_area(area)))
 	}
 }
 
+/// Parse an optional working area, missing from canvases saved before it
+fn parse_area(bytes: &[u8]) -> IResult<&[u8], Option<Rect<i32, i32>>> {
+	match opt(le_u8)(bytes)? {
+		(bytes, Some(1)) => {
+			let (bytes, area) = Rect::<i32, i32>::parse(bytes)?;
+			Ok((bytes, Some(area)))
+		}
+		(bytes, _) => Ok((bytes, None)),
+	}
+}
+
+async fn write_area<W: io::Write + std::marker::Unpin>(
+	area: Option<Rect<i32, i32>>,
+	writer: &mut W,
+) -> io::Result<usize> {
+	use async_std::io::prelude::WriteExt;
+	match area {
+		Some(area) => {
+			writer.write_all(&[1]).await?;
+			Ok(1 + area.write(writer).await?)
+		}
+		None => {
+			writer.write_all(&[0]).await?;
+			Ok(1)
+		}
+	}
+}
+
+/// Tiles are written exactly, including masked-in pixels with a zero alpha.
+/// Write a `CompactCanvas` instead to drop them from the file.
 #[async_trait(?Send)]
 impl Write for Canvas {
 	async fn write<W: io::Write + std::marker::Unpin>(&self, writer: &mut W) -> io::Result<usize> {
@@ -71,14 +106,134 @@ impl Write for Canvas {
 		let mut size = 5;
 		// Write channel
 		self.channel().write(writer).await?;
-		// Write stencils
-		let stencils = self.stencils();
+		// Write tiles
+		let tiles = self.tiles();
 		writer
-			.write_all(&(stencils.len() as u32).to_le_bytes())
+			.write_all(&(tiles.len() as u32).to_le_bytes())
 			.await?;
-		for stencil in stencils {
+		for stencil in tiles.values() {
 			size += stencil.write(writer).await?;
 		}
+		// Write working area
+		size += write_area(self.area(), writer).await?;
+		Ok(size)
+	}
+}
+
+/// Canvas compacted right before being written
+///
+/// Compacting on save is opt-in, as it drops masked-in pixels with a zero
+/// alpha. The result is parsed back as a `Canvas`.
+pub struct CompactCanvas<'a>(pub &'a Canvas);
+
+#[async_trait(?Send)]
+impl Write for CompactCanvas<'_> {
+	async fn write<W: io::Write + std::marker::Unpin>(&self, writer: &mut W) -> io::Result<usize> {
+		let (canvas, _) = self.0.compact();
+		canvas.write(writer).await
+	}
+}
+
+/// Tiles of a canvas modified since it was last saved
+///
+/// Writing a patch only writes the dirty tiles, it is then applied over the
+/// canvas parsed from the previous save.
+#[derive(Debug, Clone)]
+pub struct CanvasPatch {
+	pub channel: Channel,
+	pub area: Option<Rect<i32, i32>>,
+	/// Modified tiles, `None` for tiles left empty
+	pub tiles: BTreeMap<TileIndex, Option<Arc<Stencil>>>,
+}
+
+impl CanvasPatch {
+	/// Take the dirty tiles of a canvas, clearing them
+	pub fn take(canvas: &mut Canvas) -> Self {
+		let tiles = canvas
+			.dirty_tiles()
+			.iter()
+			.map(|index| (*index, canvas.tiles().get(index).cloned()))
+			.collect();
+		canvas.clear_dirty();
+		CanvasPatch {
+			channel: canvas.channel(),
+			area: canvas.area(),
+			tiles,
+		}
+	}
+
+	/// Apply patch over the canvas it was taken from, as previously saved
+	pub fn apply(&self, canvas: &Canvas) -> Result<Canvas, ChannelError> {
+		if canvas.channel() != self.channel {
+			return Err(ChannelError::Mismatch(canvas.channel(), self.channel));
+		}
+		let mut tiles = canvas.tiles().clone();
+		for (index, tile) in self.tiles.iter() {
+			match tile {
+				Some(tile) => tiles.insert(*index, tile.clone()),
+				None => tiles.remove(index),
+			};
+		}
+		let canvas = unsafe { Canvas::from_raw_parts(self.channel, tiles.into_values().collect()) };
+		Ok(canvas.with_area(self.area))
+	}
+}
+
+impl Parse for CanvasPatch {
+	fn parse(bytes: &[u8]) -> IResult<&[u8], CanvasPatch> {
+		// Parse channel
+		let (bytes, channel) = Channel::parse(bytes)?;
+		// Parse tiles
+		let (mut bytes, len) = le_u32(bytes)?;
+		let mut tiles = BTreeMap::new();
+		for _ in 0..len {
+			let (rest, x) = le_i32(bytes)?;
+			let (rest, y) = le_i32(rest)?;
+			let (rest, present) = le_u8(rest)?;
+			let (rest, tile) = if present == 0 {
+				(rest, None)
+			} else {
+				let (rest, stencil) = Stencil::parse(rest)?;
+				(rest, Some(Arc::new(stencil)))
+			};
+			tiles.insert((x, y), tile);
+			bytes = rest;
+		}
+		// Parse working area
+		let (bytes, area) = parse_area(bytes)?;
+		Ok((
+			bytes,
+			CanvasPatch {
+				channel,
+				area,
+				tiles,
+			},
+		))
+	}
+}
+
+#[async_trait(?Send)]
+impl Write for CanvasPatch {
+	async fn write<W: io::Write + std::marker::Unpin>(&self, writer: &mut W) -> io::Result<usize> {
+		use async_std::io::prelude::WriteExt;
+		let mut size = 5;
+		// Write channel
+		self.channel.write(writer).await?;
+		// Write tiles
+		writer
+			.write_all(&(self.tiles.len() as u32).to_le_bytes())
+			.await?;
+		for ((x, y), tile) in self.tiles.iter() {
+			writer.write_all(&x.to_le_bytes()).await?;
+			writer.write_all(&y.to_le_bytes()).await?;
+			writer.write_all(&[tile.is_some() as u8]).await?;
+			size += 9;
+			if let Some(stencil) = tile {
+				size += stencil.write(writer).await?;
+			}
+		}
+		// Write working area
+		size += write_area(self.area, writer).await?;
 		Ok(size)
 	}
 }
@@ -135,7 +290,7 @@ mod tests {
 			&vec![
 				1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 255, 255, 1, 1, 255,
 				2, 255, 3, 255, 4, 255, 5, 255, 6, 255, 7, 255, 8, 255, 9, 255, 10, 255, 11, 255,
-				12, 255, 13, 255, 14, 255, 15, 255, 16, 255
+				12, 255, 13, 255, 14, 255, 15, 255, 16, 255, 0
 			]
 		);
 
@@ -145,4 +300,140 @@ mod tests {
 		let pixels2: Vec<_> = canvas2.iter().flatten().map(|b| *b).collect();
 		assert_eq!(pixels, pixels2);
 	}
+
+	fn assert_same_tiles(a: &Canvas, b: &Canvas) {
+		assert_eq!(a.channel(), b.channel());
+		assert_eq!(a.bounds(), b.bounds());
+		assert_eq!(
+			a.tiles().keys().collect::<Vec<_>>(),
+			b.tiles().keys().collect::<Vec<_>>()
+		);
+		for (tile, tile2) in a.tiles().values().zip(b.tiles().values()) {
+			assert_eq!(tile.bounds(), tile2.bounds());
+			assert_eq!(tile.mask(), tile2.mask());
+			assert_eq!(tile.data(), tile2.data());
+		}
+	}
+
+	#[test]
+	fn canvas_round_trip() {
+		// Masked-in pixels with a zero alpha, across two tiles
+		let canvas = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(62, 0, 4, 1),
+			Channel::Lumaa,
+			vec![1, 255, 2, 0, 3, 0, 4, 255],
+		))
+		.apply_stencil(Stencil::from_buffer_mask_alpha(
+			Rect::new(0, 10, 2, 1),
+			Channel::Lumaa,
+			vec![5, 255, 0, 0],
+		))
+		.unwrap();
+		let mut buffer: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		let size = task::block_on(canvas.write(&mut buffer)).expect("Could not write");
+		assert_eq!(buffer.get_ref().len(), size);
+
+		let (rest, canvas2) = Canvas::parse(buffer.get_ref()).expect("Could not parse");
+		assert!(rest.is_empty());
+		assert_same_tiles(&canvas, &canvas2);
+		assert_eq!(canvas2.try_get(63, 0), Some(&[2, 0][..]));
+	}
+
+	#[test]
+	fn canvas_resized_round_trip() {
+		use canvas::Anchor;
+		let canvas = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(0, 0, 2, 1),
+			Channel::Rgb,
+			vec![1, 2, 3, 4, 5, 6],
+		))
+		.resize_canvas(100, 80, Anchor::Center);
+		let mut buffer: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		task::block_on(canvas.write(&mut buffer)).expect("Could not write");
+
+		let (_, canvas2) = Canvas::parse(buffer.get_ref()).expect("Could not parse");
+		assert_eq!(canvas2.bounds(), Rect::new(-49, -40, 100, 80));
+		assert_eq!(canvas2.area(), canvas.area());
+		assert_same_tiles(&canvas, &canvas2);
+		assert_eq!(canvas2.try_get(0, 0), Some(&[1, 2, 3][..]));
+		assert_eq!(canvas2.try_get(-49, -40), None);
+
+		// Compacting on save keeps the working area
+		let mut buffer: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		task::block_on(CompactCanvas(&canvas).write(&mut buffer)).expect("Could not write");
+		let (_, canvas2) = Canvas::parse(buffer.get_ref()).expect("Could not parse");
+		assert_eq!(canvas2.bounds(), Rect::new(-49, -40, 100, 80));
+
+		// Canvases saved without a working area still parse
+		let mut buffer: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		task::block_on(canvas.with_area(None).write(&mut buffer)).expect("Could not write");
+		let bytes = &buffer.get_ref()[..buffer.get_ref().len() - 1];
+		let (rest, canvas2) = Canvas::parse(bytes).expect("Could not parse");
+		assert!(rest.is_empty());
+		assert_eq!(canvas2.area(), None);
+		assert_eq!(canvas2.bounds(), Rect::new(0, 0, 2, 1));
+	}
+
+	#[test]
+	fn canvas_patch_round_trip() {
+		let mut canvas = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(62, 0, 4, 1),
+			Channel::Lumaa,
+			vec![1, 255, 2, 0, 3, 0, 4, 255],
+		));
+		let mut saved: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		task::block_on(canvas.write(&mut saved)).expect("Could not write");
+		canvas.clear_dirty();
+
+		// Paint a new tile, and empty the second tile by cropping it away
+		let mut edited = canvas
+			.apply_stencil(Stencil::from_buffer(
+				Rect::new(0, 70, 1, 1),
+				Channel::Lumaa,
+				vec![5, 255],
+			))
+			.unwrap()
+			.crop(Rect::new(0, 70, 1, 1))
+			.apply_stencil(Stencil::from_buffer(
+				Rect::new(60, 0, 1, 1),
+				Channel::Lumaa,
+				vec![6, 255],
+			))
+			.unwrap();
+		let patch = CanvasPatch::take(&mut edited);
+		assert!(edited.dirty_tiles().is_empty());
+		assert_eq!(patch.tiles.len(), 3);
+		assert!(patch.tiles[&(1, 0)].is_none());
+
+		let mut buffer: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		let size = task::block_on(patch.write(&mut buffer)).expect("Could not write");
+		assert_eq!(buffer.get_ref().len(), size);
+		let (rest, patch2) = CanvasPatch::parse(buffer.get_ref()).expect("Could not parse");
+		assert!(rest.is_empty());
+
+		let (_, saved) = Canvas::parse(saved.get_ref()).expect("Could not parse");
+		let patched = patch2.apply(&saved).unwrap();
+		assert_same_tiles(&edited, &patched);
+		assert!(patch2.apply(&Canvas::new(Channel::Rgb)).is_err());
+	}
+
+	#[test]
+	fn canvas_compact_on_save() {
+		let canvas = Canvas::from_stencil(Stencil::from_buffer(
+			Rect::new(62, 0, 4, 1),
+			Channel::Lumaa,
+			vec![1, 255, 2, 0, 3, 0, 4, 255],
+		));
+		let mut exact: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		task::block_on(canvas.write(&mut exact)).expect("Could not write");
+		let mut buffer: io::Cursor<Vec<u8>> = io::Cursor::new(Vec::new());
+		let size =
+			task::block_on(CompactCanvas(&canvas).write(&mut buffer)).expect("Could not write");
+		assert_eq!(buffer.get_ref().len(), size);
+		assert!(size < exact.get_ref().len());
+
+		let (_, canvas2) = Canvas::parse(buffer.get_ref()).expect("Could not parse");
+		assert_same_tiles(&canvas.compact().0, &canvas2);
+		assert_eq!(canvas2.try_get(63, 0), None);
+	}
 }